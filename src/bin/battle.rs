@@ -0,0 +1,118 @@
+// Steps through a day 15 battle round by round, forwards or backwards.
+//
+// Usage: battle <map> [--elf-attack N] [--save FILE]
+//        battle --load FILE
+//
+// A map is fought out first and the recording saved as JSON with `--save`; `--load` views a
+// saved recording without fighting it again.
+
+use advent_of_code_2018::combat::replay::{record, Battle};
+use advent_of_code_2018::combat::MapState;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process::exit;
+
+const HELP: &str = "Commands:
+  n [count]   next round (also just Enter)
+  p [count]   previous round
+  g <round>   go to a round; 0 is the start
+  f, l        first and last round
+  q           quit";
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} <map> [--elf-attack N] [--save FILE]\n       {} --load FILE",
+        program, program
+    );
+    exit(1);
+}
+
+fn show(battle: &Battle, round: usize) {
+    print!("{}", battle.render(round));
+    if round > 0 {
+        for action in &battle.rounds[round - 1].actions {
+            println!("  {}", battle.describe(action));
+        }
+    }
+    if round == battle.rounds.len() {
+        println!(
+            "Combat ends after {} full rounds; outcome {}",
+            battle.full_rounds(),
+            battle.outcome()
+        );
+    }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    let mut path = None;
+    let mut load = None;
+    let mut elf_attack = 3;
+    let mut save = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().cloned().unwrap_or_else(|| usage(&args[0]));
+        match arg.as_str() {
+            "--load" => load = Some(value()),
+            "--elf-attack" => elf_attack = value().parse().unwrap_or_else(|_| usage(&args[0])),
+            "--save" => save = Some(value()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => usage(&args[0]),
+        }
+    }
+
+    let battle = match (path, load) {
+        (Some(path), None) => {
+            let input = fs::read_to_string(&path).expect("Unable to read map");
+            let mut state = MapState::parse(&input);
+            state.set_rules(false, elf_attack);
+            record(&state)
+        }
+        (None, Some(file)) => {
+            let f = File::open(&file).expect("Unable to open recording");
+            serde_json::from_reader(BufReader::new(f)).unwrap_or_else(|e| {
+                eprintln!("{}: {}", file, e);
+                exit(1);
+            })
+        }
+        _ => usage(&args[0]),
+    };
+    if let Some(file) = save {
+        let out = BufWriter::new(File::create(&file).expect("Unable to create recording"));
+        serde_json::to_writer(out, &battle).expect("Unable to write recording");
+        println!("Saved {} rounds to {}", battle.rounds.len(), file);
+    }
+
+    let last = battle.rounds.len();
+    let mut round = 0;
+    show(&battle, round);
+    let stdin = io::stdin();
+    loop {
+        print!("(round {}/{}) ", round, last);
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let count = words.get(1).map(|n| n.parse::<usize>());
+        round = match (words.first().cloned().unwrap_or("n"), count) {
+            ("n", None) => (round + 1).min(last),
+            ("n", Some(Ok(n))) => round.saturating_add(n).min(last),
+            ("p", None) => round.saturating_sub(1),
+            ("p", Some(Ok(n))) => round.saturating_sub(n),
+            ("g", Some(Ok(n))) if n <= last => n,
+            ("f", None) => 0,
+            ("l", None) => last,
+            ("q", None) => break,
+            _ => {
+                println!("{}", HELP);
+                continue;
+            }
+        };
+        show(&battle, round);
+    }
+}
@@ -0,0 +1,76 @@
+// Animates the day 13 cart simulation in the terminal, or writes it to an animated GIF.
+//
+// Usage: carts <map> [--moves] [--ticks N] [--delay MS] [--gif FILE] [--scale N]
+
+use advent_of_code_2018::carts::log::record;
+use advent_of_code_2018::carts::policy::Cyclic;
+use advent_of_code_2018::carts::render::{ansi, frames, write_gif};
+use advent_of_code_2018::carts::validate::validate;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} <map> [--moves] [--ticks N] [--delay MS] [--gif FILE] [--scale N]",
+        program
+    );
+    exit(1);
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    let mut path = None;
+    let mut per_move = false;
+    let mut ticks = 1000;
+    let mut delay = 100;
+    let mut gif = None;
+    let mut scale = 4;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().cloned().unwrap_or_else(|| usage(&args[0]));
+        match arg.as_str() {
+            "--moves" => per_move = true,
+            "--ticks" => ticks = value().parse().unwrap_or_else(|_| usage(&args[0])),
+            "--delay" => delay = value().parse().unwrap_or_else(|_| usage(&args[0])),
+            "--gif" => gif = Some(value()),
+            "--scale" => scale = value().parse().unwrap_or_else(|_| usage(&args[0])),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => usage(&args[0]),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage(&args[0]));
+
+    let input = fs::read(&path).expect("Unable to read map");
+    let map = match validate(&input) {
+        Ok(map) => map,
+        Err(problems) => {
+            eprintln!("{}: {}", path, problems);
+            exit(1);
+        }
+    };
+    let frames = frames(&record(&map, &mut Cyclic, ticks), per_move);
+
+    match gif {
+        Some(file) => {
+            // GIF delays are in hundredths of a second.
+            let delay = u16::try_from(delay / 10).unwrap_or_else(|_| usage(&args[0]));
+            let out = BufWriter::new(File::create(&file).expect("Unable to create GIF"));
+            write_gif(out, &map, &frames, scale, delay).expect("Unable to write GIF");
+            println!("Wrote {} frames to {}", frames.len(), file);
+        }
+        None => {
+            print!("\x1b[2J");
+            for frame in &frames {
+                print!("{}", ansi(&map, frame));
+                std::io::stdout().flush().unwrap();
+                sleep(Duration::from_millis(delay));
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// Interactive Elfcode debugger.
+//
+// Usage: elfdbg <program> [registers]
+
+use advent_of_code_2018::elfcode::debug::{Command, Debugger, HELP};
+use advent_of_code_2018::elfcode::{trace, Machine, Program};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+fn show_registers(dbg: &Debugger) {
+    let regs = dbg
+        .machine
+        .reg
+        .iter()
+        .enumerate()
+        .map(|(i, v)| format!("r{}={}", i, v))
+        .collect::<Vec<String>>()
+        .join(" ");
+    println!("step {} ip={} {}", dbg.steps, dbg.machine.ip, regs);
+}
+
+fn show_listing(dbg: &Debugger) {
+    let ip = dbg.machine.ip;
+    let breakpoints = dbg.breakpoints().map(|(&pc, _)| pc).collect::<Vec<usize>>();
+    for (pc, instr) in dbg.machine.program.instrs.iter().enumerate() {
+        if pc + 5 < ip || pc > ip + 5 {
+            continue;
+        }
+        let marker = if pc == ip { "=>" } else { "  " };
+        let bp = if breakpoints.contains(&pc) { "*" } else { " " };
+        println!("{}{} {:>3}: {}", marker, bp, pc, instr);
+    }
+}
+
+fn execute(dbg: &mut Debugger, command: Command) {
+    match command {
+        Command::Step(n) => {
+            let stop = dbg.step(n);
+            println!("{}", stop);
+            show_registers(dbg);
+        }
+        Command::Continue => {
+            let stop = dbg.cont();
+            println!("{}", stop);
+            show_registers(dbg);
+        }
+        Command::Break(pc, cond) => dbg.add_breakpoint(pc, cond),
+        Command::Delete(pc) => {
+            if !dbg.remove_breakpoint(pc) {
+                println!("No breakpoint at {}", pc);
+            }
+        }
+        Command::Watch(reg) => dbg.add_watchpoint(reg),
+        Command::Unwatch(reg) => {
+            if !dbg.remove_watchpoint(reg) {
+                println!("Not watching r{}", reg);
+            }
+        }
+        Command::Registers => show_registers(dbg),
+        Command::SetReg(reg, value) => match dbg.machine.reg.get_mut(reg) {
+            Some(r) => *r = value,
+            None => println!("No register r{}", reg),
+        },
+        Command::SetIp(pc) => dbg.machine.ip = pc,
+        Command::List => show_listing(dbg),
+        Command::Profile(None) => {
+            let profile = dbg.machine.profile.as_ref().unwrap();
+            print!("{}", profile.render(&dbg.machine.program, 10));
+        }
+        Command::Profile(Some((pc, reg))) => {
+            if let Err(e) = dbg.machine.profile.as_mut().unwrap().watch(pc, reg) {
+                println!("{}", e);
+            }
+        }
+        Command::Trace(file, count) => {
+            let result = File::create(&file)
+                .and_then(|f| trace::write_trace(&mut dbg.machine, BufWriter::new(f), count));
+            match result {
+                Ok(steps) => {
+                    dbg.steps += steps as usize;
+                    println!("Traced {} steps to {}", steps, file);
+                }
+                Err(e) => println!("Can't write {}: {}", file, e),
+            }
+            show_registers(dbg);
+        }
+        Command::Replay(file) => match File::open(&file) {
+            Ok(f) => {
+                match trace::replay(&mut dbg.machine, BufReader::new(f)) {
+                    Ok(steps) => {
+                        dbg.steps += steps as usize;
+                        println!("Matched all {} steps", steps);
+                    }
+                    Err(divergence) => println!("{}", divergence),
+                }
+                show_registers(dbg);
+            }
+            Err(e) => println!("Can't open {}: {}", file, e),
+        },
+        Command::Help => println!("{}", HELP),
+        Command::Quit => unreachable!(),
+    }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <program> [registers]", args[0]);
+        std::process::exit(1);
+    }
+    let source = fs::read_to_string(&args[1]).expect("Unable to read program");
+    let num_regs = args
+        .get(2)
+        .map_or(6, |n| n.parse().expect("Bad register count"));
+    let program = match Program::parse_checked(&source, num_regs) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}:{}", args[1], e);
+            std::process::exit(1);
+        }
+    };
+    let mut machine = Machine::new(program, num_regs);
+    machine.enable_profiling();
+    let mut dbg = Debugger::new(machine);
+
+    show_listing(&dbg);
+    let stdin = io::stdin();
+    loop {
+        print!("(elfdbg) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match Command::parse(&line) {
+            Ok(Command::Quit) => break,
+            Ok(command) => execute(&mut dbg, command),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+// Mine cart track simulation from day 13.  Carts follow the track one square per tick, in
+// reading order, and a `JunctionPolicy` decides which way each cart turns at `+` junctions.
+
+use policy::{Cyclic, JunctionPolicy};
+use std::cmp::min;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Formatter;
+use std::mem::take;
+
+pub mod log;
+pub mod policy;
+pub mod predict;
+pub mod render;
+pub mod validate;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub enum Direction {
+    UP,
+    RIGHT,
+    DOWN,
+    LEFT,
+}
+
+impl Direction {
+    pub fn turn(self, turn: Turn) -> Direction {
+        const CLOCKWISE: [Direction; 4] = [
+            Direction::UP,
+            Direction::RIGHT,
+            Direction::DOWN,
+            Direction::LEFT,
+        ];
+        let offset = match turn {
+            Turn::LEFT => 3,
+            Turn::STRAIGHT => 0,
+            Turn::RIGHT => 1,
+        };
+        CLOCKWISE[(self as usize + offset) % 4]
+    }
+
+    /// The (dx, dy) of one step in this direction.
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            Direction::UP => (0, -1),
+            Direction::RIGHT => (1, 0),
+            Direction::DOWN => (0, 1),
+            Direction::LEFT => (-1, 0),
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            Direction::UP => '^',
+            Direction::RIGHT => '>',
+            Direction::DOWN => 'v',
+            Direction::LEFT => '<',
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub enum Turn {
+    LEFT,
+    STRAIGHT,
+    RIGHT,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Cart {
+    /// The cart's index in the original map, in reading order.
+    pub id: usize,
+    pub x: i32,
+    pub y: i32,
+    pub direction: Direction,
+    /// How many junctions the cart has passed through.
+    pub junctions: usize,
+}
+
+impl Cart {
+    pub fn new(id: usize, x: i32, y: i32, c: u8) -> Cart {
+        Cart {
+            id,
+            x,
+            y,
+            direction: match c as char {
+                '^' => Direction::UP,
+                'v' => Direction::DOWN,
+                '>' => Direction::RIGHT,
+                '<' => Direction::LEFT,
+                _ => panic!("Invalid cart character: {}", c),
+            },
+            junctions: 0,
+        }
+    }
+}
+
+impl fmt::Display for Cart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[({}, {}) {:?}]", self.x, self.y, self.direction)
+    }
+}
+
+impl PartialOrd for Cart {
+    fn partial_cmp(&self, other: &Cart) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Carts move in reading order of their positions; carts sharing a square (which only happens
+/// outside the simulation) are ordered by id.
+impl Ord for Cart {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.y, self.x, self.id).cmp(&(other.y, other.x, other.id))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Collision {
+    /// The tick it happened in: 1 is the first.
+    pub tick: u64,
+    pub x: i32,
+    pub y: i32,
+    /// The ids of the two carts, the one that moved first this tick first.
+    pub carts: (usize, usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Map {
+    pub carts: Vec<Cart>,
+    pub map: Vec<Vec<char>>,
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Carts:")?;
+        for c in &self.carts {
+            writeln!(f, "  {:?}", c)?;
+        }
+        writeln!(f, "Map:\n{}", self.get_map())?;
+        Ok(())
+    }
+}
+
+/// The index of another cart on the same square as cart `i`.
+pub fn check_collision(carts: &[Cart], i: usize) -> Option<usize> {
+    let x = carts[i].x;
+    let y = carts[i].y;
+    for (j, cart) in carts.iter().enumerate() {
+        if i == j {
+            continue;
+        }
+        if cart.x == x && cart.y == y {
+            return Some(j);
+        }
+    }
+    None
+}
+
+impl Map {
+    pub fn get_map(&self) -> String {
+        let mut outmap = self.map.clone();
+        for c in &self.carts {
+            outmap[c.y as usize][c.x as usize] = c.direction.to_char();
+        }
+        outmap
+            .iter()
+            .map(|l| l.iter().collect::<String>() + "\n")
+            .collect()
+    }
+
+    pub fn from_u8array(input: &[u8]) -> Map {
+        let mut ret = Map {
+            carts: Vec::new(),
+            map: Vec::new(),
+        };
+        let mut x = 0;
+        let mut y = 0;
+        let mut row: Vec<char> = Vec::new();
+        for c in input {
+            match char::from(*c) {
+                '^' | 'v' => {
+                    ret.carts.push(Cart::new(ret.carts.len(), x, y, *c));
+                    row.push('|');
+                }
+                '>' | '<' => {
+                    ret.carts.push(Cart::new(ret.carts.len(), x, y, *c));
+                    row.push('-');
+                }
+                '\n' => {
+                    let r: Vec<char> = take(&mut row);
+                    ret.map.push(r);
+                    y += 1;
+                    x = -1;
+                }
+                ' ' | '-' | '|' | '/' | '\\' | '+' => row.push(*c as char),
+                _ => panic!("Unexpected map character {}", *c),
+            }
+            x += 1;
+        }
+        if !row.is_empty() {
+            ret.map.push(row);
+        }
+        ret
+    }
+
+    /// The track piece at (x, y), or a space if that's off the map.
+    pub fn track(&self, x: i32, y: i32) -> char {
+        if x < 0 || y < 0 {
+            return ' ';
+        }
+        self.map
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .cloned()
+            .unwrap_or(' ')
+    }
+
+    /// Move a cart one square along the track, turning it as the track (or, at a junction,
+    /// the policy) requires.
+    pub fn advance<P: JunctionPolicy + ?Sized>(&self, cart: &mut Cart, policy: &mut P) {
+        let (dx, dy) = cart.direction.delta();
+        cart.x += dx;
+        cart.y += dy;
+        cart.direction = match (self.track(cart.x, cart.y), cart.direction) {
+            ('/', Direction::UP) | ('/', Direction::DOWN) => cart.direction.turn(Turn::RIGHT),
+            ('/', Direction::LEFT) | ('/', Direction::RIGHT) => cart.direction.turn(Turn::LEFT),
+            ('\\', Direction::UP) | ('\\', Direction::DOWN) => cart.direction.turn(Turn::LEFT),
+            ('\\', Direction::LEFT) | ('\\', Direction::RIGHT) => cart.direction.turn(Turn::RIGHT),
+            ('|', Direction::UP) | ('|', Direction::DOWN) => cart.direction,
+            ('-', Direction::LEFT) | ('-', Direction::RIGHT) => cart.direction,
+            ('+', _) => {
+                let turn = policy.turn(cart);
+                cart.junctions += 1;
+                cart.direction.turn(turn)
+            }
+            (track, _) => panic!("Invalid map state '{}': cart={}", track, cart),
+        };
+    }
+
+    /// Run one tick with the puzzle's left, straight, right junction cycle.
+    pub fn update(&mut self, clear_collisions: bool) -> Option<Cart> {
+        self.update_with(&mut Cyclic, clear_collisions)
+    }
+
+    /// Run one tick.  Without `clear_collisions`, stops at the first collision and returns the
+    /// cart that was hit; otherwise both carts in each collision are removed.
+    pub fn update_with<P: JunctionPolicy + ?Sized>(
+        &mut self,
+        policy: &mut P,
+        clear_collisions: bool,
+    ) -> Option<Cart> {
+        // First, ensure the carts are sorted.
+        self.carts.sort();
+
+        let mut i: usize = 0;
+        while i < self.carts.len() {
+            let mut cart = self.carts[i];
+            self.advance(&mut cart, policy);
+            self.carts[i] = cart;
+            // Each time we move a cart, we need to check for collisions.
+            match check_collision(&self.carts, i) {
+                Some(j) if !clear_collisions => return Some(self.carts[min(i, j)]),
+                Some(j) => {
+                    // Don't advance i in this case...
+                    self.carts.remove(j);
+                    if j < i {
+                        i -= 1;
+                    }
+                    self.carts.remove(i);
+                }
+                None => i += 1,
+            }
+        }
+        // No collisions occurred during this update
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turn() {
+        assert_eq!(Direction::LEFT, Direction::UP.turn(Turn::LEFT));
+        assert_eq!(Direction::UP, Direction::LEFT.turn(Turn::RIGHT));
+        assert_eq!(Direction::DOWN, Direction::DOWN.turn(Turn::STRAIGHT));
+    }
+
+    #[test]
+    fn test_check_collision() {
+        let inputs = &[
+            (
+                vec![
+                    Cart::new(0, 0, 0, b'>'),
+                    Cart::new(1, 0, 1, b'v'),
+                    Cart::new(2, 0, 1, b'^'),
+                ],
+                1,
+                Some(2),
+            ),
+            (
+                vec![
+                    Cart::new(0, 0, 0, b'>'),
+                    Cart::new(1, 0, 1, b'v'),
+                    Cart::new(2, 0, 1, b'^'),
+                ],
+                2,
+                Some(1),
+            ),
+            (
+                vec![
+                    Cart::new(0, 0, 0, b'>'),
+                    Cart::new(1, 0, 1, b'v'),
+                    Cart::new(2, 0, 2, b'^'),
+                ],
+                1,
+                None,
+            ),
+        ];
+        for t in inputs.iter() {
+            assert_eq!(check_collision(&t.0, t.1), t.2)
+        }
+    }
+}
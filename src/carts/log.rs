@@ -0,0 +1,235 @@
+// A complete record of one run of the simulation, removing carts as they crash: every move,
+// crash and removal in order, and each cart's position tick by tick.  Questions about the run
+// are then answered from the record rather than by simulating again.
+
+use super::policy::JunctionPolicy;
+use super::{check_collision, Cart, Collision, Map};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A cart moved one square; this is the cart afterwards.
+    Moved {
+        tick: u64,
+        cart: Cart,
+    },
+    Crash(Collision),
+    Removed {
+        tick: u64,
+        id: usize,
+    },
+}
+
+impl Event {
+    pub fn tick(&self) -> u64 {
+        match *self {
+            Event::Moved { tick, .. } | Event::Removed { tick, .. } => tick,
+            Event::Crash(collision) => collision.tick,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Log {
+    pub events: Vec<Event>,
+    /// For each cart id, the cart at the start and after each tick.  A crashed cart's path
+    /// ends with where it was removed.
+    pub paths: BTreeMap<usize, Vec<Cart>>,
+    /// How many ticks ran.
+    pub ticks: u64,
+}
+
+/// When `record_until` stops, if it hasn't run out of ticks first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Until {
+    /// At the end of the tick with the first crash.
+    FirstCrash,
+    /// Once at most one cart is left.
+    LastCart,
+}
+
+/// Run until at most one cart is left, or for `max_ticks` ticks.
+pub fn record<P: JunctionPolicy + ?Sized>(map: &Map, policy: &mut P, max_ticks: u64) -> Log {
+    record_until(map, policy, max_ticks, Until::LastCart)
+}
+
+/// Run until `until`, or for `max_ticks` ticks.
+pub fn record_until<P: JunctionPolicy + ?Sized>(
+    map: &Map,
+    policy: &mut P,
+    max_ticks: u64,
+    until: Until,
+) -> Log {
+    let mut map = map.clone();
+    let mut log = Log {
+        events: Vec::new(),
+        paths: map.carts.iter().map(|c| (c.id, vec![*c])).collect(),
+        ticks: 0,
+    };
+    let mut crashed = false;
+    while log.ticks < max_ticks && map.carts.len() > 1 && !(crashed && until == Until::FirstCrash) {
+        log.ticks += 1;
+        let tick = log.ticks;
+        map.carts.sort();
+        let mut i = 0;
+        while i < map.carts.len() {
+            let mut cart = map.carts[i];
+            map.advance(&mut cart, policy);
+            map.carts[i] = cart;
+            log.events.push(Event::Moved { tick, cart });
+            let j = match check_collision(&map.carts, i) {
+                Some(j) => j,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let other = map.carts[j];
+            // Carts before this one in the list have already moved this tick.
+            let carts = if j < i {
+                (other.id, cart.id)
+            } else {
+                (cart.id, other.id)
+            };
+            log.events.push(Event::Crash(Collision {
+                tick,
+                x: cart.x,
+                y: cart.y,
+                carts,
+            }));
+            crashed = true;
+            for removed in [other, cart].iter() {
+                log.events.push(Event::Removed {
+                    tick,
+                    id: removed.id,
+                });
+                log.paths.get_mut(&removed.id).unwrap().push(*removed);
+            }
+            map.carts.remove(j);
+            if j < i {
+                i -= 1;
+            }
+            map.carts.remove(i);
+        }
+        for cart in &map.carts {
+            log.paths.get_mut(&cart.id).unwrap().push(*cart);
+        }
+    }
+    log
+}
+
+impl Log {
+    pub fn crashes(&self) -> impl Iterator<Item = &Collision> {
+        self.events.iter().filter_map(|e| match e {
+            Event::Crash(collision) => Some(collision),
+            _ => None,
+        })
+    }
+
+    /// The ids of the removed carts, with the tick each went in.
+    pub fn removals(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.events.iter().filter_map(|e| match *e {
+            Event::Removed { tick, id } => Some((tick, id)),
+            _ => None,
+        })
+    }
+
+    pub fn path(&self, id: usize) -> &[Cart] {
+        &self.paths[&id]
+    }
+
+    /// The cart with this id after `tick` ticks, if it was still running then.
+    pub fn cart_at(&self, id: usize, tick: u64) -> Option<Cart> {
+        let path = self.path(id);
+        let removed = self.removals().any(|(_, r)| r == id);
+        if removed && tick as usize + 1 >= path.len() {
+            return None;
+        }
+        path.get(tick as usize).cloned()
+    }
+
+    /// The cart that moved first in the first crash, as it was just after the crash.
+    pub fn first_crash(&self) -> Option<Cart> {
+        let collision = self.crashes().next()?;
+        self.path(collision.carts.0).last().cloned()
+    }
+
+    /// The carts still running at the end.
+    pub fn survivors(&self) -> Vec<Cart> {
+        let removed = self.removals().map(|(_, id)| id).collect::<Vec<usize>>();
+        self.paths
+            .iter()
+            .filter(|(id, _)| !removed.contains(id))
+            .map(|(_, path)| *path.last().unwrap())
+            .collect()
+    }
+
+    /// The only cart left, if all the others crashed.
+    pub fn last_cart(&self) -> Option<Cart> {
+        match self.survivors()[..] {
+            [cart] => Some(cart),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::policy::Cyclic;
+    use super::*;
+
+    const INPUT: &[u8] = b"/>-<\\  \n|   |  \n| /<+-\\\n| | | v\n\\>+</ |\n  |   ^\n  \\<->/\n";
+
+    #[test]
+    fn test_record() {
+        let map = Map::from_u8array(INPUT);
+        let log = record(&map, &mut Cyclic, 100);
+        assert_eq!(3, log.ticks);
+        assert_eq!(
+            vec![
+                (1, 2, 0, (0, 1)),
+                (1, 2, 4, (4, 5)),
+                (1, 6, 4, (3, 6)),
+                (3, 2, 4, (2, 7)),
+            ],
+            log.crashes()
+                .map(|c| (c.tick, c.x, c.y, c.carts))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(8, log.removals().count());
+        assert_eq!(Some((1, 0)), log.removals().next());
+
+        let last = log.last_cart().unwrap();
+        assert_eq!((8, (6, 4)), (last.id, (last.x, last.y)));
+        assert_eq!(4, log.path(8).len());
+        assert_eq!((6, 6), (log.path(8)[1].x, log.path(8)[1].y));
+
+        // Carts 0 and 1 meet in the middle in the first tick.
+        assert_eq!(2, log.path(1).len());
+        assert_eq!((2, 0), (log.path(1)[1].x, log.path(1)[1].y));
+        assert_eq!((2, 0), (log.path(0)[1].x, log.path(0)[1].y));
+        assert_eq!(Some(log.path(0)[1]), log.first_crash());
+        assert_eq!(None, log.cart_at(0, 1));
+        assert_eq!(Some(log.path(0)[0]), log.cart_at(0, 0));
+        assert_eq!(Some(last), log.cart_at(8, 3));
+    }
+
+    #[test]
+    fn test_until_first_crash() {
+        let map = Map::from_u8array(INPUT);
+        let log = record_until(&map, &mut Cyclic, 100, Until::FirstCrash);
+        assert_eq!(1, log.ticks);
+        assert_eq!(3, log.crashes().count());
+        assert_eq!(Some(log.path(0)[1]), log.first_crash());
+    }
+
+    #[test]
+    fn test_max_ticks() {
+        let map = Map::from_u8array(INPUT);
+        let log = record(&map, &mut Cyclic, 1);
+        assert_eq!(1, log.ticks);
+        assert_eq!(None, log.last_cart());
+        assert_eq!(3, log.survivors().len());
+        assert!(log.events.iter().all(|e| e.tick() == 1));
+    }
+}
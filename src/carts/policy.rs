@@ -0,0 +1,132 @@
+// Junction policies: which way a cart turns each time it reaches a `+`.
+
+use super::{Cart, Turn};
+use crate::rng::Rng;
+use std::collections::HashMap;
+
+pub trait JunctionPolicy {
+    /// The turn `cart` takes at the junction it has just reached.  `cart.junctions` counts the
+    /// junctions it passed before this one.
+    fn turn(&mut self, cart: &Cart) -> Turn;
+}
+
+/// The puzzle's rule: left, then straight, then right, repeating.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cyclic;
+
+impl Cyclic {
+    pub const CYCLE: [Turn; 3] = [Turn::LEFT, Turn::STRAIGHT, Turn::RIGHT];
+}
+
+impl JunctionPolicy for Cyclic {
+    fn turn(&mut self, cart: &Cart) -> Turn {
+        Cyclic::CYCLE[cart.junctions % Cyclic::CYCLE.len()]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlwaysStraight;
+
+impl JunctionPolicy for AlwaysStraight {
+    fn turn(&mut self, _cart: &Cart) -> Turn {
+        Turn::STRAIGHT
+    }
+}
+
+/// Turns chosen uniformly at random, reproducibly from a seed.
+#[derive(Clone, Debug)]
+pub struct Random {
+    rng: Rng,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random {
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl JunctionPolicy for Random {
+    fn turn(&mut self, _cart: &Cart) -> Turn {
+        Cyclic::CYCLE[self.rng.below(3) as usize]
+    }
+}
+
+/// A repeating list of turns for each cart, by id.  Carts without a script use the fallback.
+#[derive(Clone, Debug)]
+pub struct Scripted<P> {
+    pub scripts: HashMap<usize, Vec<Turn>>,
+    pub fallback: P,
+}
+
+impl<P: JunctionPolicy> Scripted<P> {
+    pub fn new(fallback: P) -> Scripted<P> {
+        Scripted {
+            scripts: HashMap::new(),
+            fallback,
+        }
+    }
+
+    pub fn script(mut self, id: usize, turns: &[Turn]) -> Scripted<P> {
+        self.scripts.insert(id, turns.to_vec());
+        self
+    }
+}
+
+impl<P: JunctionPolicy> JunctionPolicy for Scripted<P> {
+    fn turn(&mut self, cart: &Cart) -> Turn {
+        match self.scripts.get(&cart.id) {
+            Some(turns) if !turns.is_empty() => turns[cart.junctions % turns.len()],
+            _ => self.fallback.turn(cart),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Map;
+    use super::*;
+
+    // Two carts on a figure-of-eight through one junction.
+    const EIGHT: &[u8] = b"/-\\  \n| |  \n\\-+-\\\n  | |\n  \\-/\n";
+
+    fn run<P: JunctionPolicy>(policy: &mut P, ticks: usize) -> Cart {
+        let mut map = Map::from_u8array(EIGHT);
+        map.carts.push(Cart::new(0, 1, 0, b'>'));
+        for _ in 0..ticks {
+            assert_eq!(None, map.update_with(policy, false));
+        }
+        map.carts[0]
+    }
+
+    #[test]
+    fn test_policies() {
+        // The junction is 3 ticks from the start and each loop is 8 ticks long.  Going straight
+        // on follows the figure of eight; turning sends the cart round the same loop again.
+        let cart = run(&mut AlwaysStraight, 16);
+        assert_eq!((1, 0, 2), (cart.x, cart.y, cart.junctions));
+
+        let cart = run(&mut Cyclic, 16);
+        assert_eq!((0, 1, 2), (cart.x, cart.y, cart.junctions));
+
+        let mut right = Scripted::new(Cyclic).script(0, &[Turn::RIGHT]);
+        let cart = run(&mut right, 12);
+        assert_eq!((1, 2, 2), (cart.x, cart.y, cart.junctions));
+
+        let mut unscripted = Scripted::new(AlwaysStraight).script(1, &[Turn::RIGHT]);
+        assert_eq!(run(&mut AlwaysStraight, 16), run(&mut unscripted, 16));
+    }
+
+    #[test]
+    fn test_random() {
+        let cart = Cart::new(0, 0, 0, b'>');
+        let turns = |seed| {
+            let mut random = Random::new(seed);
+            (0..30).map(|_| random.turn(&cart)).collect::<Vec<Turn>>()
+        };
+        assert_eq!(turns(1), turns(1));
+        assert_ne!(turns(1), turns(2));
+        assert!(Cyclic::CYCLE.iter().all(|t| turns(3).contains(t)));
+    }
+}
@@ -0,0 +1,631 @@
+// Collision prediction without simulating tick by tick, for the puzzle's cyclic junction policy.
+//
+// A cart's state (position, direction, and place in the left/straight/right cycle) determines
+// the state it came from, so on its own every cart runs round a pure cycle of states.  Its
+// position after t ticks is a function of t modulo the cycle length, and whether two carts can
+// meet at tick t comes down to a pair of congruences, solved with the Chinese remainder theorem.
+//
+// Two carts crash during tick t if, after it, they are on the same square, or if one moves onto
+// the square the other started the tick on before the other has moved away.
+//
+// Carts often share a cycle, differing only in how far round it they start, or run round
+// cycles of the same length.  For those pairs the crashes come from a table, built once per
+// pair of cycles, of which places on the two cycles are on the same square.
+
+use super::policy::Cyclic;
+use super::{Cart, Collision, Direction, Map};
+use std::collections::HashMap;
+
+type Key = (i32, i32, Direction, usize);
+
+/// What determines a cart's next state.
+fn key(cart: &Cart) -> Key {
+    (
+        cart.x,
+        cart.y,
+        cart.direction,
+        cart.junctions % Cyclic::CYCLE.len(),
+    )
+}
+
+/// The states a cart passes through, starting from its position at tick 0.
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub states: Vec<Cart>,
+    /// Junctions passed in one trip round the cycle.
+    pub junctions: usize,
+}
+
+impl Route {
+    pub fn new(map: &Map, cart: &Cart) -> Route {
+        let start = key(cart);
+        let mut states = vec![*cart];
+        let mut current = *cart;
+        loop {
+            map.advance(&mut current, &mut Cyclic);
+            if key(&current) == start {
+                break;
+            }
+            states.push(current);
+        }
+        Route {
+            states,
+            junctions: current.junctions - cart.junctions,
+        }
+    }
+
+    /// The route of `cart`, which starts `place` states round this one.
+    fn rotated(&self, cart: &Cart, place: usize) -> Route {
+        let period = self.states.len();
+        let start = self.states[place].junctions;
+        let states = (place..place + period)
+            .map(|p| {
+                let (state, laps) = (self.states[p % period], p / period);
+                Cart {
+                    id: cart.id,
+                    junctions: state.junctions + laps * self.junctions - start + cart.junctions,
+                    ..state
+                }
+            })
+            .collect();
+        Route {
+            states,
+            junctions: self.junctions,
+        }
+    }
+
+    pub fn period(&self) -> u64 {
+        self.states.len() as u64
+    }
+
+    /// The cart after `tick` ticks, if nothing stops it.
+    pub fn at(&self, tick: u64) -> Cart {
+        let mut cart = self.states[(tick % self.period()) as usize];
+        cart.junctions += (tick / self.period()) as usize * self.junctions;
+        cart
+    }
+
+    fn position(&self, residue: u64) -> (i32, i32) {
+        let cart = &self.states[(residue % self.period()) as usize];
+        (cart.x, cart.y)
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Solves t = a (mod m), t = b (mod n) for fixed moduli.
+struct Crt {
+    m: i64,
+    g: i64,
+    /// n / g
+    ng: i64,
+    /// The inverse of m / g, modulo n / g.
+    inv: i64,
+}
+
+impl Crt {
+    fn new(m: u64, n: u64) -> Crt {
+        let (m, n) = (m as i64, n as i64);
+        let g = gcd(m, n);
+        let ng = n / g;
+        let (mut old_r, mut r, mut old_s, mut s) = ((m / g).rem_euclid(ng), ng, 1i64, 0i64);
+        while r != 0 {
+            let q = old_r / r;
+            old_r -= q * r;
+            std::mem::swap(&mut old_r, &mut r);
+            old_s -= q * s;
+            std::mem::swap(&mut old_s, &mut s);
+        }
+        Crt {
+            m,
+            g,
+            ng,
+            inv: old_s,
+        }
+    }
+
+    /// The smallest t >= 1 that solves the congruences, if any.
+    fn solve(&self, a: u64, b: u64) -> Option<u64> {
+        let (a, b) = (a as i64, b as i64);
+        if (b - a) % self.g != 0 {
+            return None;
+        }
+        let k = ((b - a) / self.g % self.ng * self.inv).rem_euclid(self.ng);
+        let lcm = self.m * self.ng;
+        let t = (a + self.m * k).rem_euclid(lcm);
+        Some(if t == 0 { lcm } else { t } as u64)
+    }
+}
+
+/// For each square, the ticks (modulo the period) at which a route is there.
+struct Visits {
+    width: usize,
+    ticks: Vec<Vec<u64>>,
+}
+
+impl Visits {
+    fn new(map: &Map, route: &Route) -> Visits {
+        let width = map.map.iter().map(Vec::len).max().unwrap_or(0);
+        let mut ticks = vec![Vec::new(); width * map.map.len()];
+        for (u, cart) in route.states.iter().enumerate() {
+            ticks[cart.y as usize * width + cart.x as usize].push(u as u64);
+        }
+        Visits { width, ticks }
+    }
+
+    fn at(&self, (x, y): (i32, i32)) -> &[u64] {
+        &self.ticks[y as usize * self.width + x as usize]
+    }
+}
+
+/// Carts move in reading order of where they start the tick.
+fn order((x, y): (i32, i32)) -> (i32, i32) {
+    (y, x)
+}
+
+/// A pair's first crash, with the reading-order key of the cart whose move causes it.
+type Crash = (u64, (i32, i32), Collision);
+
+#[derive(Default)]
+struct Earliest(Option<Crash>);
+
+impl Earliest {
+    /// A crash on `square` at `tick`, caused by the cart that started the tick on
+    /// `mover_start`; `first` moved before `second`.
+    fn consider(
+        &mut self,
+        tick: u64,
+        mover_start: (i32, i32),
+        first: usize,
+        second: usize,
+        square: (i32, i32),
+    ) {
+        let candidate = (
+            tick,
+            order(mover_start),
+            Collision {
+                tick,
+                x: square.0,
+                y: square.1,
+                carts: (first, second),
+            },
+        );
+        if self
+            .0
+            .is_none_or(|b| (b.0, b.1) > (candidate.0, candidate.1))
+        {
+            self.0 = Some(candidate);
+        }
+    }
+}
+
+/// The first tick at which two carts would crash if nothing else got in the way.
+fn first_crash(
+    (i, route_i): (usize, &Route),
+    (j, route_j): (usize, &Route),
+    visits_j: &Visits,
+) -> Option<Crash> {
+    let (li, lj) = (route_i.period(), route_j.period());
+    let crt = Crt::new(li, lj);
+    let mut best = Earliest::default();
+    let mut consider = |t: Option<u64>, mover_start, first, second, square| {
+        if let Some(tick) = t {
+            best.consider(tick, mover_start, first, second, square);
+        }
+    };
+    for u in 0..li {
+        let square = route_i.position(u);
+        let prev_i = route_i.position(u + li - 1);
+        for &v in visits_j.at(square) {
+            // Only v = u - 1, u or u + 1 modulo the gcd of the periods can ever line up.
+            let offset = (v + crt.g as u64 - u % crt.g as u64) % crt.g as u64;
+            if offset > 1 && offset + 1 != crt.g as u64 {
+                continue;
+            }
+            let prev_j = route_j.position(v + lj - 1);
+            // Both end the tick on `square`; the second to move crashes into the first.
+            let (first, second, mover) = if order(prev_i) < order(prev_j) {
+                (i, j, prev_j)
+            } else {
+                (j, i, prev_i)
+            };
+            consider(crt.solve(u, v), mover, first, second, square);
+            // i moves onto `square` while j is still there, j moving next tick.
+            if order(prev_i) < order(square) {
+                consider(crt.solve(u, v + 1), prev_i, i, j, square);
+            }
+            // j moves onto `square` while i is still there.
+            if order(prev_j) < order(square) {
+                consider(crt.solve(u + 1, v), prev_j, j, i, square);
+            }
+        }
+    }
+    best.0
+}
+
+/// For two routes round cycles of the same length and each distance d, the places p where
+/// place p on the first and place p + d on the second are on the same square.
+struct Meets {
+    /// Where each distance's places start in `places`.
+    starts: Vec<usize>,
+    places: Vec<u64>,
+}
+
+impl Meets {
+    fn new(first: &Route, second: &Visits) -> Meets {
+        let len = first.period();
+        let pairs = || {
+            first.states.iter().enumerate().flat_map(|(p, cart)| {
+                let p = p as u64;
+                let places = second.at((cart.x, cart.y));
+                places
+                    .iter()
+                    .map(move |&q| (((q + len - p) % len) as usize, p))
+            })
+        };
+        let mut starts = vec![0; len as usize + 1];
+        for (d, _) in pairs() {
+            starts[d + 1] += 1;
+        }
+        for d in 0..len as usize {
+            starts[d + 1] += starts[d];
+        }
+        let mut next = starts.clone();
+        let mut places = vec![0; starts[len as usize]];
+        for (d, p) in pairs() {
+            places[next[d]] = p;
+            next[d] += 1;
+        }
+        Meets { starts, places }
+    }
+
+    fn at(&self, distance: u64) -> &[u64] {
+        let d = distance as usize;
+        &self.places[self.starts[d]..self.starts[d + 1]]
+    }
+}
+
+/// `first_crash` for two carts starting `di` and `dj` states round routes of the same length,
+/// with the table of where the two routes meet.
+fn first_crash_on_cycles(
+    (i, di, route_i): (usize, u64, &Route),
+    (j, dj, route_j): (usize, u64, &Route),
+    meets: &Meets,
+) -> Option<Crash> {
+    let len = route_i.period();
+    let (pos_i, pos_j) = (|p| route_i.position(p), |p| route_j.position(p));
+    // How far ahead of i j is.
+    let d = (dj + len - di) % len;
+    let mut best = Earliest::default();
+    // The tick at which i ends up at `place`.
+    let tick = |place: u64| match (place + len - di) % len {
+        0 => len,
+        t => t,
+    };
+    // Both end the tick on the same square; the second to move crashes into the first.
+    for &a in meets.at(d) {
+        let (square, prev_i, prev_j) = (pos_i(a), pos_i(a + len - 1), pos_j(a + d + len - 1));
+        if order(prev_i) < order(prev_j) {
+            best.consider(tick(a), prev_j, i, j, square);
+        } else {
+            best.consider(tick(a), prev_i, j, i, square);
+        }
+    }
+    // i moves onto the square j starts the tick on.
+    for &a in meets.at((d + len - 1) % len) {
+        let (square, prev_i) = (pos_i(a), pos_i(a + len - 1));
+        if order(prev_i) < order(square) {
+            best.consider(tick(a), prev_i, i, j, square);
+        }
+    }
+    // j moves onto the square i starts the tick on.
+    for &p in meets.at((d + 1) % len) {
+        let a = p + 1;
+        let (square, prev_j) = (pos_j(a + d), pos_j(a + d + len - 1));
+        if order(prev_j) < order(square) {
+            best.consider(tick(a), prev_j, j, i, square);
+        }
+    }
+    best.0
+}
+
+/// Every crash that happens when carts are removed as they collide, in order, and the routes
+/// of all the carts.
+#[derive(Clone, Debug)]
+pub struct Prediction {
+    pub routes: Vec<Route>,
+    pub collisions: Vec<Collision>,
+}
+
+pub fn predict(map: &Map) -> Prediction {
+    // Each cart's route, which cycle it runs round, and how far round it the route starts.
+    // Carts on a cycle already found don't need to be followed round it again.
+    let mut routes: Vec<Route> = Vec::new();
+    // The route each cycle was found from, and where it goes.
+    let mut cycles: Vec<(usize, Visits)> = Vec::new();
+    let mut places = Vec::new();
+    for cart in &map.carts {
+        let found = cycles.iter().enumerate().find_map(|(c, (r, visits))| {
+            let on_square = visits.at((cart.x, cart.y)).iter();
+            let mut on_cycle =
+                on_square.filter(|&&p| key(&routes[*r].states[p as usize]) == key(cart));
+            on_cycle.next().map(|&p| (c, p))
+        });
+        let route = match found {
+            Some((c, p)) => {
+                places.push((c, p));
+                routes[cycles[c].0].rotated(cart, p as usize)
+            }
+            None => {
+                let route = Route::new(map, cart);
+                places.push((cycles.len(), 0));
+                cycles.push((routes.len(), Visits::new(map, &route)));
+                route
+            }
+        };
+        routes.push(route);
+    }
+    let mut tables = HashMap::new();
+    let mut visits: Vec<Option<Visits>> = routes.iter().map(|_| None).collect();
+
+    // Each pair's first crash is the only one that matters: afterwards neither cart exists.
+    let mut crashes = Vec::new();
+    for i in 0..routes.len() {
+        for j in i + 1..routes.len() {
+            let ((ci, di), (cj, dj)) = (places[i], places[j]);
+            let (ri, rj) = (cycles[ci].0, cycles[cj].0);
+            let crash = if routes[ri].period() == routes[rj].period() {
+                // One table serves both orders of a pair of cycles.
+                let (first, second) = if ci <= cj {
+                    ((i, di, &routes[ri]), (j, dj, &routes[rj]))
+                } else {
+                    ((j, dj, &routes[rj]), (i, di, &routes[ri]))
+                };
+                let table = tables
+                    .entry((ci.min(cj), ci.max(cj)))
+                    .or_insert_with(|| Meets::new(first.2, &cycles[ci.max(cj)].1));
+                first_crash_on_cycles(first, second, table)
+            } else {
+                let visits_j = visits[j].get_or_insert_with(|| Visits::new(map, &routes[j]));
+                first_crash((i, &routes[i]), (j, &routes[j]), visits_j)
+            };
+            crashes.extend(crash);
+        }
+    }
+    crashes.sort_by_key(|&(tick, key, _)| (tick, key));
+
+    let mut alive = vec![true; routes.len()];
+    let mut collisions = Vec::new();
+    for (_, _, mut collision) in crashes {
+        let (a, b) = collision.carts;
+        if alive[a] && alive[b] {
+            alive[a] = false;
+            alive[b] = false;
+            collision.carts = (map.carts[a].id, map.carts[b].id);
+            collisions.push(collision);
+        }
+    }
+    Prediction { routes, collisions }
+}
+
+impl Prediction {
+    fn index(&self, id: usize) -> usize {
+        self.routes
+            .iter()
+            .position(|r| r.states[0].id == id)
+            .expect("No such cart")
+    }
+
+    /// The cart with this id after `tick` ticks, ignoring whether it has crashed.
+    pub fn cart_at(&self, id: usize, tick: u64) -> Cart {
+        self.routes[self.index(id)].at(tick)
+    }
+
+    /// The cart that moved first in the first crash, as it was just after the crash.
+    pub fn first_crash(&self) -> Option<Cart> {
+        let c = self.collisions.first()?;
+        Some(self.cart_at(c.carts.0, c.tick))
+    }
+
+    /// The only cart left, at the end of the tick of the last crash, if all the others crash.
+    pub fn last_cart(&self) -> Option<Cart> {
+        let crashed = self.collisions.len() * 2;
+        if crashed + 1 != self.routes.len() {
+            return None;
+        }
+        let tick = self.collisions.last().map_or(0, |c| c.tick);
+        let survivor = self.routes.iter().map(|r| r.states[0].id).find(|&id| {
+            self.collisions
+                .iter()
+                .all(|c| c.carts.0 != id && c.carts.1 != id)
+        })?;
+        Some(self.cart_at(survivor, tick))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::log::record;
+    use super::*;
+    use crate::rng::Rng;
+    use std::time::Instant;
+
+    #[test]
+    fn test_crt() {
+        assert_eq!(Some(8), Crt::new(3, 5).solve(2, 3));
+        assert_eq!(Some(15), Crt::new(3, 5).solve(0, 0));
+        assert_eq!(Some(10), Crt::new(6, 4).solve(4, 2));
+        assert_eq!(None, Crt::new(6, 4).solve(1, 2));
+    }
+
+    #[test]
+    fn test_route() {
+        let map = Map::from_u8array(b"/>-\\\n|  |\n\\--/\n");
+        let route = Route::new(&map, &map.carts[0]);
+        assert_eq!(10, route.period());
+        assert_eq!(0, route.junctions);
+        let far = route.at(8_000_000_001);
+        assert_eq!((2, 0), (far.x, far.y));
+
+        // A figure of eight: the turn cycle only repeats once the cart has passed through the
+        // junction a multiple of three times.
+        let map = Map::from_u8array(b"/>\\  \n| |  \n\\-+-\\\n  | |\n  \\-/\n");
+        let route = Route::new(&map, &map.carts[0]);
+        assert_eq!(48, route.period());
+        assert_eq!(6, route.junctions);
+        let cart = route.states[5];
+        assert_eq!(
+            Cart {
+                junctions: cart.junctions + 60,
+                ..cart
+            },
+            route.at(5 + 10 * route.period())
+        );
+    }
+
+    // Random loops of track with carts scattered on them.
+    fn random_map(seed: u64) -> Map {
+        random_map_sized(seed, (12, 10), 4, 7)
+    }
+
+    fn random_map_sized(seed: u64, (w, h): (usize, usize), loops: usize, max_carts: usize) -> Map {
+        let mut rng = Rng::new(seed);
+        let mut grid = vec![vec![' '; w]; h];
+        for _ in 0..loops {
+            let x0 = rng.below(w as u64 - 2) as usize;
+            let y0 = rng.below(h as u64 - 2) as usize;
+            let x1 = x0 + 2 + rng.below((w - x0 - 2) as u64) as usize;
+            let y1 = y0 + 2 + rng.below((h - y0 - 2) as u64) as usize;
+            // Only keep rectangles whose edges cross existing track at right angles.
+            let edge = |x, y| {
+                if (x == x0 || x == x1) && (y == y0 || y == y1) {
+                    None
+                } else if x == x0 || x == x1 {
+                    Some('|')
+                } else {
+                    Some('-')
+                }
+            };
+            let mut cells = Vec::new();
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    if x != x0 && x != x1 && y != y0 && y != y1 {
+                        continue;
+                    }
+                    let new = match edge(x, y) {
+                        None if (x == x0) == (y == y0) => '/',
+                        None => '\\',
+                        Some(c) => c,
+                    };
+                    cells.push((x, y, new));
+                }
+            }
+            let fits = cells.iter().all(|&(x, y, new)| {
+                matches!((grid[y][x], new), (' ', _) | ('|', '-') | ('-', '|'))
+            });
+            if fits {
+                for (x, y, new) in cells {
+                    grid[y][x] = if grid[y][x] == ' ' { new } else { '+' };
+                }
+            }
+        }
+        let mut carts = 0;
+        for row in grid.iter_mut() {
+            for c in row.iter_mut() {
+                let cart = match (*c, rng.below(4)) {
+                    ('-', 0) => '>',
+                    ('-', 1) => '<',
+                    ('|', 0) => '^',
+                    ('|', 1) => 'v',
+                    _ => continue,
+                };
+                if carts < max_carts && rng.below(3) == 0 {
+                    *c = cart;
+                    carts += 1;
+                }
+            }
+        }
+        let text = grid
+            .iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect::<String>();
+        Map::from_u8array(text.as_bytes())
+    }
+
+    #[test]
+    fn test_matches_simulation() {
+        const TICKS: u64 = 5000;
+        for seed in 0..300 {
+            let map = random_map(seed);
+            let prediction = predict(&map);
+            let within = |c: &Option<&Collision>| c.is_some_and(|c| c.tick <= TICKS);
+
+            let mut sim = map.clone();
+            let first = (0..TICKS).find_map(|_| sim.update(false));
+            if within(&prediction.collisions.first()) {
+                assert_eq!(first, prediction.first_crash(), "seed {}", seed);
+            } else {
+                assert_eq!(None, first, "seed {}", seed);
+            }
+
+            let mut sim = map.clone();
+            let mut ticks = 0;
+            while sim.carts.len() > 1 && ticks < TICKS {
+                sim.update(true);
+                ticks += 1;
+            }
+            if prediction.collisions.last().is_none_or(|c| c.tick <= TICKS) {
+                let survivor = Some(&sim.carts[..]).filter(|c| c.len() == 1);
+                assert_eq!(
+                    survivor.map(|c| c[0]),
+                    prediction.last_cart(),
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_map() {
+        for seed in 0..4 {
+            let map = random_map_sized(seed, (150, 150), 60, 40);
+            let prediction = predict(&map);
+            let ticks = prediction.collisions.last().map_or(0, |c| c.tick);
+            let log = record(&map, &mut Cyclic, ticks);
+            let crashes = log.crashes().cloned().collect::<Vec<Collision>>();
+            assert_eq!(crashes, prediction.collisions, "seed {}", seed);
+        }
+    }
+
+    // Carts on these maps outlast a million ticks: predicting every crash should take well under
+    // the time simulating that many does.  Run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_large_map_timing() {
+        for &seed in [0, 2, 3].iter() {
+            let map = random_map_sized(seed, (150, 150), 60, 40);
+            let start = Instant::now();
+            let prediction = predict(&map);
+            let predicted = start.elapsed();
+            let start = Instant::now();
+            let log = record(&map, &mut Cyclic, 1_000_000);
+            let simulated = start.elapsed();
+            assert_eq!(1_000_000, log.ticks);
+            assert_eq!(log.crashes().count(), prediction.collisions.len());
+            assert!(
+                predicted * 2 < simulated,
+                "seed {}: {:?} vs {:?}",
+                seed,
+                predicted,
+                simulated
+            );
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// Animations of the cart simulation, for seeing the order carts move in: frames after every
+// tick or every single cart move, drawn as ANSI terminal output or an animated GIF.  Carts are
+// removed as they crash, and crash sites stay marked with an `X` for the rest of the run.
+
+use super::log::{Event, Log};
+use super::{Cart, Map};
+use gif::SetParameter;
+use std::io;
+use std::io::Write;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// The tick in progress: 1 is the first.  Frame 0 is the starting layout.
+    pub tick: u64,
+    /// The cart that just moved, for frames after a single move.
+    pub moved: Option<usize>,
+    pub carts: Vec<Cart>,
+    /// Every crash so far.
+    pub crashes: Vec<(i32, i32)>,
+    /// Carts removed since the previous frame.
+    pub removed: Vec<usize>,
+}
+
+/// Replay a recorded run.  With `per_move`, there's a frame after each cart moves, not just
+/// after each tick.
+pub fn frames(log: &Log, per_move: bool) -> Vec<Frame> {
+    let mut frame = Frame {
+        tick: 0,
+        moved: None,
+        carts: log.paths.values().map(|path| path[0]).collect(),
+        crashes: Vec::new(),
+        removed: Vec::new(),
+    };
+    let mut frames = vec![frame.clone()];
+    let mut events = log.events.iter().peekable();
+    while let Some(event) = events.next() {
+        frame.tick = event.tick();
+        match *event {
+            Event::Moved { cart, .. } => {
+                if let Some(c) = frame.carts.iter_mut().find(|c| c.id == cart.id) {
+                    *c = cart;
+                }
+                if per_move {
+                    frame.moved = Some(cart.id);
+                }
+            }
+            Event::Crash(collision) => frame.crashes.push((collision.x, collision.y)),
+            Event::Removed { id, .. } => {
+                frame.removed.push(id);
+                frame.carts.retain(|c| c.id != id);
+            }
+        }
+        let done = match events.peek() {
+            None => true,
+            Some(Event::Moved { .. }) if per_move => true,
+            Some(next) => next.tick() != frame.tick,
+        };
+        if done {
+            frames.push(frame.clone());
+            frame.removed.clear();
+        }
+    }
+    frames
+}
+
+const RESET: &str = "\x1b[0m";
+const TRACK: &str = "\x1b[2m";
+const CART: &str = "\x1b[1;32m";
+const MOVED: &str = "\x1b[1;30;43m";
+const CRASH: &str = "\x1b[1;31m";
+
+/// One frame as ANSI text: the cursor is moved to the top left first, so printing frames one
+/// after another animates in place.
+pub fn ansi(map: &Map, frame: &Frame) -> String {
+    let mut out = String::from("\x1b[H");
+    for (y, row) in map.map.iter().enumerate() {
+        for (x, &track) in row.iter().enumerate() {
+            let (x, y) = (x as i32, y as i32);
+            let cart = frame.carts.iter().find(|c| (c.x, c.y) == (x, y));
+            match cart {
+                Some(cart) => {
+                    let colour = if frame.moved == Some(cart.id) {
+                        MOVED
+                    } else {
+                        CART
+                    };
+                    out.push_str(colour);
+                    out.push(cart.direction.to_char());
+                }
+                None if frame.crashes.contains(&(x, y)) => {
+                    out.push_str(CRASH);
+                    out.push('X');
+                }
+                None => {
+                    out.push_str(TRACK);
+                    out.push(track);
+                }
+            }
+            out.push_str(RESET);
+        }
+        out.push('\n');
+    }
+    out.push_str(&format!(
+        "tick {}  carts {}  crashes {}",
+        frame.tick,
+        frame.carts.len(),
+        frame.crashes.len()
+    ));
+    if let Some(id) = frame.moved {
+        out.push_str(&format!("  moved #{}", id));
+    }
+    if !frame.removed.is_empty() {
+        let ids = frame
+            .removed
+            .iter()
+            .map(|id| format!("#{}", id))
+            .collect::<Vec<String>>();
+        out.push_str(&format!("  {}removed {}{}", CRASH, ids.join(" "), RESET));
+    }
+    // Clear whatever a longer status line left behind.
+    out.push_str("\x1b[K\n");
+    out
+}
+
+/// Background, track, cart, moved cart, crash.
+const PALETTE: [u8; 15] = [
+    0x10, 0x10, 0x10, 0x60, 0x60, 0x60, 0x20, 0xc0, 0x20, 0xff, 0xd0, 0x00, 0xff, 0x20, 0x20,
+];
+
+/// Each square's colour index in the GIF palette.
+fn pixels(map: &Map, frame: &Frame, width: usize) -> Vec<u8> {
+    let mut pixels = vec![0; width * map.map.len()];
+    for (y, row) in map.map.iter().enumerate() {
+        for (x, &track) in row.iter().enumerate() {
+            if track != ' ' {
+                pixels[y * width + x] = 1;
+            }
+        }
+    }
+    for &(x, y) in &frame.crashes {
+        pixels[y as usize * width + x as usize] = 4;
+    }
+    for cart in &frame.carts {
+        let moved = frame.moved == Some(cart.id);
+        pixels[cart.y as usize * width + cart.x as usize] = if moved { 3 } else { 2 };
+    }
+    pixels
+}
+
+/// Write the frames as a looping animated GIF, each square `scale` pixels across and each
+/// frame shown for `delay` hundredths of a second.
+pub fn write_gif<W: Write>(
+    out: W,
+    map: &Map,
+    frames: &[Frame],
+    scale: usize,
+    delay: u16,
+) -> io::Result<()> {
+    let width = map.map.iter().map(Vec::len).max().unwrap_or(0);
+    let height = map.map.len();
+    let (w, h) = (width * scale, height * scale);
+    if w > usize::from(u16::MAX) || h > usize::from(u16::MAX) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Map too big"));
+    }
+    let mut encoder = gif::Encoder::new(out, w as u16, h as u16, &PALETTE)?;
+    encoder.set(gif::Repeat::Infinite)?;
+    for frame in frames {
+        let squares = pixels(map, frame, width);
+        let mut scaled = Vec::with_capacity(w * h);
+        for row in squares.chunks(width.max(1)) {
+            let line = row
+                .iter()
+                .flat_map(|&p| std::iter::repeat_n(p, scale))
+                .collect::<Vec<u8>>();
+            for _ in 0..scale {
+                scaled.extend_from_slice(&line);
+            }
+        }
+        let mut image = gif::Frame::from_indexed_pixels(w as u16, h as u16, &scaled, None);
+        image.delay = delay;
+        encoder.write_frame(&image)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::log::record;
+    use super::super::policy::Cyclic;
+    use super::*;
+
+    const INPUT: &[u8] = b"/>-<\\  \n|   |  \n| /<+-\\\n| | | v\n\\>+</ |\n  |   ^\n  \\<->/\n";
+
+    #[test]
+    fn test_frames() {
+        let map = Map::from_u8array(INPUT);
+        let ticks = frames(&record(&map, &mut Cyclic, 10), false);
+        // The puzzle example is down to one cart after three ticks.
+        assert_eq!(4, ticks.len());
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            ticks.iter().map(|f| f.tick).collect::<Vec<u64>>()
+        );
+        assert_eq!(vec![(2, 0), (2, 4), (6, 4)], ticks[1].crashes[..3].to_vec());
+        assert_eq!(1, ticks[3].carts.len());
+        assert_eq!((6, 4), (ticks[3].carts[0].x, ticks[3].carts[0].y));
+
+        // Frames after each move end up in the same place.
+        let moves = frames(&record(&map, &mut Cyclic, 10), true);
+        let last = moves.last().unwrap();
+        assert_eq!(ticks[3].carts, last.carts);
+        assert_eq!(ticks[3].crashes, last.crashes);
+        let removed = moves.iter().map(|f| f.removed.len()).sum::<usize>();
+        assert_eq!(8, removed);
+    }
+
+    #[test]
+    fn test_ansi() {
+        let map = Map::from_u8array(b"/>\\\n\\-/\n");
+        let ticks = frames(&record(&map, &mut Cyclic, 1), true);
+        assert_eq!(1, ticks.len());
+        let text = ansi(&map, &ticks[0]);
+        assert!(text.starts_with("\x1b[H\x1b[2m/\x1b[0m\x1b[1;32m>\x1b[0m"));
+        assert!(text.ends_with("tick 0  carts 1  crashes 0\x1b[K\n"));
+
+        let map = Map::from_u8array(b"/>-<\\\n\\---/\n");
+        let moves = frames(&record(&map, &mut Cyclic, 1), true);
+        assert_eq!(3, moves.len());
+        assert!(ansi(&map, &moves[1]).contains("\x1b[1;30;43m>\x1b[0m"));
+        let text = ansi(&map, &moves[2]);
+        assert!(text.contains("\x1b[1;31mX\x1b[0m"));
+        assert!(text.contains("moved #1  \x1b[1;31mremoved #0 #1\x1b[0m"));
+    }
+
+    #[test]
+    fn test_gif() {
+        let map = Map::from_u8array(INPUT);
+        let ticks = frames(&record(&map, &mut Cyclic, 10), false);
+        let mut out = Vec::new();
+        write_gif(&mut out, &map, &ticks, 2, 50).unwrap();
+        assert!(out.starts_with(b"GIF89a"));
+
+        let mut decoder = gif::Decoder::new(&out[..]);
+        decoder.set(gif::ColorOutput::Indexed);
+        let mut reader = decoder.read_info().unwrap();
+        assert_eq!((14, 14), (reader.width(), reader.height()));
+        let mut count = 0;
+        while let Some(frame) = reader.read_next_frame().unwrap() {
+            assert_eq!(50, frame.delay);
+            if count == 0 {
+                // The cart at (1, 0) fills pixels 2 and 3 of the top two rows.
+                assert_eq!(&[1, 1, 2, 2, 1, 1], &frame.buffer[..6]);
+                assert_eq!(&[1, 1, 2, 2, 1, 1], &frame.buffer[14..20]);
+            }
+            count += 1;
+        }
+        assert_eq!(ticks.len(), count);
+    }
+}
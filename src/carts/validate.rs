@@ -0,0 +1,325 @@
+// Checks a track layout before simulating it: every piece of track must lead to a neighbour
+// that leads back, and every cart must sit on track running its way.  Everything wrong is
+// reported at once, rather than as a panic when the first cart finds it.
+//
+// A corner can't say which way it turns on its own: `/` joins right and down, or up and left.
+// Each corner is first settled on the readings of it that connect to something that could lead
+// back, and then every piece is checked against its neighbours' settled readings.
+
+use super::{Direction, Map};
+use std::fmt;
+use std::fmt::Formatter;
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::UP,
+    Direction::RIGHT,
+    Direction::DOWN,
+    Direction::LEFT,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    BadCharacter {
+        x: i32,
+        y: i32,
+        c: char,
+    },
+    /// The track at (x, y) leads in `direction` to an empty square.
+    DeadEnd {
+        x: i32,
+        y: i32,
+        direction: Direction,
+    },
+    /// The track at (x, y) leads in `direction` to a piece that doesn't lead back.
+    Mismatch {
+        x: i32,
+        y: i32,
+        direction: Direction,
+        neighbour: char,
+    },
+    /// The cart at (x, y) isn't on straight track running its way.
+    CartOffTrack {
+        x: i32,
+        y: i32,
+        cart: char,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Problem::BadCharacter { x, y, c } => write!(f, "({}, {}): bad character {:?}", x, y, c),
+            Problem::DeadEnd { x, y, direction } => {
+                write!(f, "({}, {}): dead end going {:?}", x, y, direction)
+            }
+            Problem::Mismatch {
+                x,
+                y,
+                direction,
+                neighbour,
+            } => write!(
+                f,
+                "({}, {}): track going {:?} meets '{}'",
+                x, y, direction, neighbour
+            ),
+            Problem::CartOffTrack { x, y, cart } => {
+                write!(f, "({}, {}): cart '{}' isn't on matching track", x, y, cart)
+            }
+        }
+    }
+}
+
+/// All the problems with a map, in reading order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problems(pub Vec<Problem>);
+
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} problem(s) with the track:", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problems {}
+
+fn opposite(direction: Direction) -> Direction {
+    DIRECTIONS[(direction as usize + 2) % 4]
+}
+
+/// The ways each reading of a track piece leads.
+fn readings(piece: char) -> &'static [&'static [Direction]] {
+    use self::Direction::*;
+    match piece {
+        '-' => &[&[LEFT, RIGHT]],
+        '|' => &[&[UP, DOWN]],
+        '+' => &[&[UP, RIGHT, DOWN, LEFT]],
+        '/' => &[&[RIGHT, DOWN], &[UP, LEFT]],
+        '\\' => &[&[LEFT, DOWN], &[UP, RIGHT]],
+        _ => &[],
+    }
+}
+
+fn leads(piece: char, direction: Direction) -> bool {
+    readings(piece).iter().any(|r| r.contains(&direction))
+}
+
+/// The ways each piece leads, by row: every reading of it with no problems given the
+/// neighbours' possible readings, or if there are none, the one with the fewest.
+fn settle(map: &Map) -> Vec<Vec<Vec<Direction>>> {
+    let possible = |x: i32, y: i32, direction| leads(map.track(x, y), direction);
+    map.map
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, &piece)| {
+                    let (x, y) = (x as i32, y as i32);
+                    let checked = readings(piece)
+                        .iter()
+                        .map(|r| (r, check(map, x, y, r, &possible).len()))
+                        .collect::<Vec<_>>();
+                    let connected = checked
+                        .iter()
+                        .filter(|(_, problems)| *problems == 0)
+                        .flat_map(|(r, _)| r.iter().cloned())
+                        .collect::<Vec<Direction>>();
+                    if !connected.is_empty() {
+                        connected
+                    } else {
+                        checked
+                            .iter()
+                            .min_by_key(|(_, problems)| *problems)
+                            .map_or_else(Vec::new, |(r, _)| r.to_vec())
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The problems with one reading of the piece at (x, y), given which ways its neighbours lead.
+fn check(
+    map: &Map,
+    x: i32,
+    y: i32,
+    reading: &[Direction],
+    leads: &dyn Fn(i32, i32, Direction) -> bool,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for &direction in reading {
+        let (dx, dy) = direction.delta();
+        let neighbour = map.track(x + dx, y + dy);
+        if neighbour == ' ' {
+            problems.push(Problem::DeadEnd { x, y, direction });
+        } else if !leads(x + dx, y + dy, opposite(direction)) {
+            problems.push(Problem::Mismatch {
+                x,
+                y,
+                direction,
+                neighbour,
+            });
+        }
+    }
+    problems
+}
+
+/// Parse a map, reporting every problem with it.
+pub fn validate(input: &[u8]) -> Result<Map, Problems> {
+    let mut problems = Vec::new();
+    // Replace anything unparseable with empty space so the rest can still be checked.
+    let mut clean = input.to_vec();
+    let (mut x, mut y) = (0, 0);
+    for c in clean.iter_mut() {
+        match *c {
+            b'\n' => {
+                x = 0;
+                y += 1;
+                continue;
+            }
+            b' ' | b'-' | b'|' | b'/' | b'\\' | b'+' | b'^' | b'v' | b'<' | b'>' => (),
+            _ => {
+                problems.push(Problem::BadCharacter {
+                    x,
+                    y,
+                    c: *c as char,
+                });
+                *c = b' ';
+            }
+        }
+        x += 1;
+    }
+    let map = Map::from_u8array(&clean);
+
+    let settled = settle(&map);
+    let settled_leads = |x: i32, y: i32, direction| {
+        settled
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .is_some_and(|ways| ways.contains(&direction))
+    };
+    for (y, row) in settled.iter().enumerate() {
+        for (x, ways) in row.iter().enumerate() {
+            let (x, y) = (x as i32, y as i32);
+            let cart = map.carts.iter().find(|c| (c.x, c.y) == (x, y));
+            let found = check(&map, x, y, ways, &settled_leads);
+            match cart {
+                Some(cart) if !found.is_empty() => problems.push(Problem::CartOffTrack {
+                    x,
+                    y,
+                    cart: cart.direction.to_char(),
+                }),
+                _ => problems.extend(found),
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(map)
+    } else {
+        problems.sort_by_key(|p| match *p {
+            Problem::BadCharacter { x, y, .. }
+            | Problem::DeadEnd { x, y, .. }
+            | Problem::Mismatch { x, y, .. }
+            | Problem::CartOffTrack { x, y, .. } => (y, x),
+        });
+        Err(Problems(problems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid() {
+        let input = b"/->-\\        \n|   |  /----\\\n| /-+--+-\\  |\n| | |  | v  |\n\\-+-/  \\-+--/\n  \\------/   \n";
+        let map = validate(input).unwrap();
+        assert_eq!(2, map.carts.len());
+        // Corners next to each other, both readings of each needed.
+        assert!(validate(b"/\\\n\\/\n").is_ok());
+    }
+
+    #[test]
+    fn test_problems() {
+        let problems = validate(b"/-\\ \n| - \n\\-/x\n").unwrap_err();
+        assert_eq!(
+            vec![
+                Problem::Mismatch {
+                    x: 2,
+                    y: 0,
+                    direction: Direction::DOWN,
+                    neighbour: '-'
+                },
+                Problem::DeadEnd {
+                    x: 2,
+                    y: 1,
+                    direction: Direction::LEFT
+                },
+                Problem::DeadEnd {
+                    x: 2,
+                    y: 1,
+                    direction: Direction::RIGHT
+                },
+                Problem::Mismatch {
+                    x: 2,
+                    y: 2,
+                    direction: Direction::UP,
+                    neighbour: '-'
+                },
+                Problem::BadCharacter { x: 3, y: 2, c: 'x' },
+            ],
+            problems.0
+        );
+
+        // The '\\' at (5, 2) can only turn up and right, so the track and the cart leading
+        // into it from the left would run off it.
+        let problems = validate(b"/>\\  /-\\\n| |  | |\n\\-/>-\\-/\n").unwrap_err();
+        assert_eq!(
+            vec![
+                Problem::CartOffTrack {
+                    x: 3,
+                    y: 2,
+                    cart: '>'
+                },
+                Problem::Mismatch {
+                    x: 4,
+                    y: 2,
+                    direction: Direction::RIGHT,
+                    neighbour: '\\'
+                },
+            ],
+            problems.0
+        );
+    }
+
+    #[test]
+    fn test_cart_off_track() {
+        // A cart drawn on a corner, where the parser assumes straight track.
+        let problems = validate(b"/-->\n|  |\n\\--/\n").unwrap_err();
+        assert_eq!(
+            vec![
+                Problem::CartOffTrack {
+                    x: 3,
+                    y: 0,
+                    cart: '>'
+                },
+                Problem::Mismatch {
+                    x: 3,
+                    y: 1,
+                    direction: Direction::UP,
+                    neighbour: '-'
+                },
+            ],
+            problems.0
+        );
+        assert_eq!(
+            "2 problem(s) with the track:\n  (3, 0): cart '>' isn't on matching track\n  \
+             (3, 1): track going UP meets '-'",
+            problems.to_string()
+        );
+    }
+}
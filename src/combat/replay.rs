@@ -0,0 +1,266 @@
+// Recorded battles: every move, attack and death in each round, and the units left standing
+// after it, so a battle can be stepped through in either direction or saved as JSON.
+
+use super::{EndState, Entity, MapState};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    Move {
+        id: usize,
+        from: (usize, usize),
+        to: (usize, usize),
+    },
+    /// `hp` is what the target has left afterwards.
+    Attack {
+        attacker: usize,
+        target: usize,
+        damage: i32,
+        hp: i32,
+    },
+    Death {
+        id: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unit {
+    pub id: usize,
+    /// `G` or `E`.
+    pub kind: char,
+    pub x: usize,
+    pub y: usize,
+    pub hp: i32,
+}
+
+impl From<&Entity> for Unit {
+    fn from(e: &Entity) -> Unit {
+        Unit {
+            id: e.id,
+            kind: e.entity_type,
+            x: e.x,
+            y: e.y,
+            hp: e.hp,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Round {
+    /// The first round is 1.
+    pub number: usize,
+    pub actions: Vec<Action>,
+    /// The units still alive at the end of the round, in reading order.
+    pub units: Vec<Unit>,
+    /// `NotFinished` if every unit had its turn.
+    pub end: EndState,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Battle {
+    /// Walls and open floor, without the units.
+    pub map: Vec<String>,
+    pub elf_attack: i32,
+    /// The units at the start.
+    pub units: Vec<Unit>,
+    /// Every round, up to and including the one the battle ended in.
+    pub rounds: Vec<Round>,
+}
+
+fn living(state: &MapState) -> Vec<Unit> {
+    let mut units = state
+        .entities
+        .iter()
+        .filter(|e| e.hp > 0)
+        .map(Unit::from)
+        .collect::<Vec<Unit>>();
+    units.sort_by_key(|u| (u.y, u.x));
+    units
+}
+
+/// Fight the battle to the end, recording every round.
+pub fn record(state: &MapState) -> Battle {
+    let mut state = state.clone();
+    let mut battle = Battle {
+        map: state.map.iter().map(|row| row.iter().collect()).collect(),
+        elf_attack: state.elf_atk,
+        units: living(&state),
+        rounds: Vec::new(),
+    };
+    loop {
+        let mut actions = Vec::new();
+        let end = state.execute_round_with(&mut actions);
+        battle.rounds.push(Round {
+            number: battle.rounds.len() + 1,
+            actions,
+            units: living(&state),
+            end,
+        });
+        if end != EndState::NotFinished {
+            return battle;
+        }
+    }
+}
+
+impl Battle {
+    /// The number of rounds every unit had its turn in.
+    pub fn full_rounds(&self) -> usize {
+        self.rounds
+            .iter()
+            .filter(|r| r.end == EndState::NotFinished)
+            .count()
+    }
+
+    /// The units left after `round` rounds; round 0 is the start.
+    pub fn units_after(&self, round: usize) -> &[Unit] {
+        match round {
+            0 => &self.units,
+            _ => &self.rounds[round - 1].units,
+        }
+    }
+
+    /// Full rounds times the hit points left, as the puzzle scores it.
+    pub fn outcome(&self) -> i32 {
+        let last = self.units_after(self.rounds.len());
+        self.full_rounds() as i32 * last.iter().map(|u| u.hp).sum::<i32>()
+    }
+
+    fn name(&self, id: usize) -> String {
+        let kind = self
+            .units
+            .iter()
+            .find(|u| u.id == id)
+            .map_or('?', |u| u.kind);
+        format!("{}{}", kind, id)
+    }
+
+    pub fn describe(&self, action: &Action) -> String {
+        match *action {
+            Action::Move { id, from, to } => format!(
+                "{} moves from {},{} to {},{}",
+                self.name(id),
+                from.0,
+                from.1,
+                to.0,
+                to.1
+            ),
+            Action::Attack {
+                attacker,
+                target,
+                damage,
+                hp,
+            } => format!(
+                "{} hits {} for {}, leaving {}",
+                self.name(attacker),
+                self.name(target),
+                damage,
+                hp
+            ),
+            Action::Death { id } => format!("{} dies", self.name(id)),
+        }
+    }
+
+    /// The map after `round` rounds, with each row's units and their hit points beside it, as
+    /// in the puzzle's examples.
+    pub fn render(&self, round: usize) -> String {
+        let units = self.units_after(round);
+        let mut out = match round {
+            0 => String::from("Initially:\n"),
+            1 => String::from("After 1 round:\n"),
+            _ => format!("After {} rounds:\n", round),
+        };
+        for (y, line) in self.map.iter().enumerate() {
+            let mut row = line.chars().collect::<Vec<char>>();
+            let mut panel = Vec::new();
+            for u in units.iter().filter(|u| u.y == y) {
+                row[u.x] = u.kind;
+                panel.push(format!("{}({})", u.kind, u.hp));
+            }
+            out.extend(row);
+            if !panel.is_empty() {
+                out.push_str("   ");
+                out.push_str(&panel.join(", "));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "#######\n#.G...#\n#...EG#\n#.#.#G#\n#..G#E#\n#.....#\n#######\n";
+
+    #[test]
+    fn test_record() {
+        let battle = record(&MapState::parse(INPUT));
+        assert_eq!(47, battle.full_rounds());
+        assert_eq!(48, battle.rounds.len());
+        assert_eq!(EndState::NoEnemies, battle.rounds[47].end);
+        assert_eq!(27730, battle.outcome());
+
+        assert_eq!(
+            "After 1 round:\n\
+             #######\n\
+             #..G..#   G(200)\n\
+             #...EG#   E(197), G(197)\n\
+             #.#G#G#   G(200), G(197)\n\
+             #...#E#   E(197)\n\
+             #.....#\n\
+             #######\n",
+            battle.render(1)
+        );
+        assert_eq!(
+            "After 47 rounds:\n\
+             #######\n\
+             #G....#   G(200)\n\
+             #.G...#   G(131)\n\
+             #.#.#G#   G(59)\n\
+             #...#.#\n\
+             #....G#   G(200)\n\
+             #######\n",
+            battle.render(47)
+        );
+        assert!(battle
+            .render(0)
+            .starts_with("Initially:\n#######\n#.G...#   G(200)\n"));
+    }
+
+    #[test]
+    fn test_actions() {
+        let battle = record(&MapState::parse(INPUT));
+        let first = &battle.rounds[0].actions;
+        assert_eq!(
+            Action::Move {
+                id: 0,
+                from: (2, 1),
+                to: (3, 1)
+            },
+            first[0]
+        );
+        assert_eq!("G0 moves from 2,1 to 3,1", battle.describe(&first[0]));
+        let attack = first
+            .iter()
+            .find(|a| matches!(a, Action::Attack { .. }))
+            .unwrap();
+        assert_eq!("E1 hits G2 for 3, leaving 197", battle.describe(attack));
+
+        // Every unit that isn't left standing died exactly once.
+        let deaths = battle
+            .rounds
+            .iter()
+            .flat_map(|r| &r.actions)
+            .filter(|a| matches!(a, Action::Death { .. }))
+            .count();
+        assert_eq!(battle.units.len() - 4, deaths);
+    }
+
+    #[test]
+    fn test_json() {
+        let battle = record(&MapState::parse(INPUT));
+        let json = serde_json::to_string(&battle).unwrap();
+        assert_eq!(battle, serde_json::from_str::<Battle>(&json).unwrap());
+    }
+}
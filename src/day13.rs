@@ -1,300 +1,61 @@
-// Deriving FromPrimitive causes a clippy lint error
-#![allow(clippy::useless_attribute)]
-
 use aoc_runner_derive::{aoc, aoc_generator};
-use num_traits::FromPrimitive;
-use std::cmp::min;
-use std::cmp::Ordering;
-use std::fmt;
-use std::fmt::Formatter;
-use std::mem::replace;
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
-enum Direction {
-    UP,
-    RIGHT,
-    DOWN,
-    LEFT,
-}
-
-#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, Eq, Ord, PartialOrd)]
-enum TurnState {
-    LEFT,
-    STRAIGHT,
-    RIGHT,
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub struct Cart {
-    x: i32,
-    y: i32,
-    direction: Direction,
-    turn_state: TurnState,
-}
-
-impl Cart {
-    fn new(x: i32, y: i32, c: u8) -> Cart {
-        Cart {
-            x,
-            y,
-            direction: match c as char {
-                '^' => Direction::UP,
-                'v' => Direction::DOWN,
-                '>' => Direction::RIGHT,
-                '<' => Direction::LEFT,
-                _ => panic!("Invalid cart character: {}", c),
-            },
-            turn_state: TurnState::LEFT,
-        }
-    }
-}
-
-impl fmt::Display for Cart {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[({}, {}) {:?}]", self.x, self.y, self.direction)
-    }
-}
-
-impl PartialOrd for Cart {
-    fn partial_cmp(&self, other: &Cart) -> Option<Ordering> {
-        if self.x == other.x && self.y == other.y {
-            return None;
-        }
-        if self.y == other.y {
-            return Some(self.x.cmp(&other.x));
-        }
-        Some(self.y.cmp(&other.y))
-    }
-}
-
-impl Ord for Cart {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self.partial_cmp(&other) {
-            r @ Some(_) => r.unwrap(),
-            None => {
-                if self.direction == other.direction {
-                    return self.turn_state.cmp(&other.turn_state);
-                }
-                self.direction.cmp(&other.direction)
-            }
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct Map {
-    carts: Vec<Cart>,
-    map: Vec<Vec<char>>,
-}
-
-impl fmt::Display for Map {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        writeln!(f, "Carts:")?;
-        for i in 0..self.carts.len() {
-            let c = &self.carts[i];
-            writeln!(f, "  {:?}", c)?;
-        }
-        writeln!(f, "Map:\n{}", self.get_map())?;
-        Ok(())
-    }
-}
 
-fn check_collision(carts: &[Cart], i: usize) -> Option<usize> {
-    let x = carts[i].x;
-    let y = carts[i].y;
-    for (j, cart) in carts.iter().enumerate() {
-        if i == j {
-            continue;
-        }
-        if cart.x == x && cart.y == y {
-            return Some(j);
-        }
-    }
-    None
-}
-
-impl Map {
-    fn get_map(&self) -> String {
-        let mut outmap = self.map.clone();
-        for i in 0..self.carts.len() {
-            let c = &self.carts[i];
-            outmap[c.y as usize][c.x as usize] = match c.direction {
-                Direction::LEFT => '<',
-                Direction::RIGHT => '>',
-                Direction::UP => '^',
-                Direction::DOWN => 'v',
-            }
-        }
-        outmap
-            .iter()
-            .map(|l| l.iter().collect::<String>() + "\n")
-            .collect()
-    }
-
-    fn from_u8array(input: &[u8]) -> Map {
-        let mut ret = Map {
-            carts: Vec::new(),
-            map: Vec::new(),
-        };
-        let mut x = 0;
-        let mut y = 0;
-        let mut row: Vec<char> = Vec::new();
-        for c in input {
-            match char::from(*c) {
-                '^' | 'v' => {
-                    ret.carts.push(Cart::new(x, y, *c));
-                    row.push('|');
-                }
-                '>' | '<' => {
-                    ret.carts.push(Cart::new(x, y, *c));
-                    row.push('-');
-                }
-                '\n' => {
-                    let r: Vec<char> = replace(&mut row, Vec::new());
-                    ret.map.push(r);
-                    y += 1;
-                    x = -1;
-                }
-                ' ' | '-' | '|' | '/' | '\\' | '+' => row.push(*c as char),
-                _ => panic!("Unexpected map character {}", *c),
-            }
-            x += 1;
-        }
-        if !row.is_empty() {
-            ret.map.push(row);
-        }
-        ret
-    }
-
-    fn update(&mut self, clear_collisions: bool) -> Option<Cart> {
-        // First, ensure the carts are sorted.
-        self.carts.sort();
-
-        let mut i: usize = 0;
-        while i < self.carts.len() {
-            // Update each cart state using a mutable reference to the current cart.
-            {
-                let mut cart: Cart = self.carts[i];
-                // Move the cart along the track..
-                match cart.direction {
-                    Direction::UP => cart.y -= 1,
-                    Direction::DOWN => cart.y += 1,
-                    Direction::LEFT => cart.x -= 1,
-                    Direction::RIGHT => cart.x += 1,
-                }
-                // Turn the cart if needed.
-                cart.direction = match (self.map[cart.y as usize][cart.x as usize], cart.direction)
-                {
-                    ('/', Direction::UP) => Direction::RIGHT,
-                    ('/', Direction::RIGHT) => Direction::UP,
-                    ('/', Direction::DOWN) => Direction::LEFT,
-                    ('/', Direction::LEFT) => Direction::DOWN,
-                    ('\\', Direction::UP) => Direction::LEFT,
-                    ('\\', Direction::LEFT) => Direction::UP,
-                    ('\\', Direction::RIGHT) => Direction::DOWN,
-                    ('\\', Direction::DOWN) => Direction::RIGHT,
-                    ('|', Direction::UP) | ('|', Direction::DOWN) => cart.direction,
-                    ('-', Direction::LEFT) | ('-', Direction::RIGHT) => cart.direction,
-                    ('+', _) => {
-                        let t = cart.turn_state;
-                        cart.turn_state = match FromPrimitive::from_u8(t as u8 + 1) {
-                            Some(t) => t,
-                            None => FromPrimitive::from_u8(0).unwrap(),
-                        };
-                        match t {
-                            TurnState::LEFT => match cart.direction {
-                                Direction::UP => Direction::LEFT,
-                                Direction::LEFT => Direction::DOWN,
-                                Direction::DOWN => Direction::RIGHT,
-                                Direction::RIGHT => Direction::UP,
-                            },
-                            TurnState::RIGHT => match cart.direction {
-                                Direction::UP => Direction::RIGHT,
-                                Direction::RIGHT => Direction::DOWN,
-                                Direction::DOWN => Direction::LEFT,
-                                Direction::LEFT => Direction::UP,
-                            },
-                            TurnState::STRAIGHT => cart.direction,
-                        }
-                    }
-                    (_, _) => panic!(
-                        "Invalid map state '{}': cart={}",
-                        self.map[cart.y as usize][cart.x as usize], cart
-                    ),
-                };
-                self.carts[i] = cart;
-            }
-            // Each time we move a cart, we need to check for collisions.
-            let c = match check_collision(&self.carts, i) {
-                Some(j) if !clear_collisions => Some(self.carts[min(i, j)]),
-                Some(j) => {
-                    // Don't advance i in this case...
-                    self.carts.remove(j);
-                    if j < i {
-                        i -= 1;
-                    }
-                    self.carts.remove(i);
-                    None
-                }
-                None => {
-                    i += 1;
-                    None
-                }
-            };
-            if c.is_some() {
-                return c;
-            }
-        }
-        // No collisions occurred during this update
-        None
-    }
-}
+use crate::carts::log::{record, record_until, Until};
+use crate::carts::policy::Cyclic;
+use crate::carts::predict::predict;
+use crate::carts::validate::{validate, Problems};
+use crate::carts::{Cart, Map};
 
 #[aoc_generator(day13)]
-pub fn parse(input: &[u8]) -> Map {
-    Map::from_u8array(input)
+pub fn parse(input: &[u8]) -> Result<Map, Problems> {
+    validate(input)
 }
 
 #[aoc(day13, part1)]
 pub fn solve_part1(input: &Map) -> Cart {
-    let mut map = input.clone();
-    let mut c: Option<Cart> = None;
-    while c.is_none() {
-        c = map.update(false)
-        //print!("{}", map);
-    }
-    c.unwrap()
+    record_until(input, &mut Cyclic, u64::MAX, Until::FirstCrash)
+        .first_crash()
+        .expect("No carts crash")
 }
 
 #[aoc(day13, part2)]
 pub fn solve_part2(input: &Map) -> Cart {
-    let mut map = input.clone();
-    while map.carts.len() > 1 {
-        map.update(true);
-        //print!("{}", map);
-    }
-    map.carts[0]
+    record(input, &mut Cyclic, u64::MAX)
+        .last_cart()
+        .expect("No single cart survives")
+}
+
+#[aoc(day13, part1, predicted)]
+pub fn solve_part1_predicted(input: &Map) -> Cart {
+    predict(input).first_crash().expect("No carts crash")
+}
+
+#[aoc(day13, part2, predicted)]
+pub fn solve_part2_predicted(input: &Map) -> Cart {
+    predict(input).last_cart().expect("No single cart survives")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::carts::Direction;
 
     const INPUT: &[u8] = b"/->-\\        \n|   |  /----\\\n| /-+--+-\\  |\n| | |  | v  |\n\\-+-/  \\-+--/\n  \\------/   \n";
     const INPUT2: &[u8] = b"/>-<\\  \n|   |  \n| /<+-\\\n| | | v\n\\>+</ |\n  |   ^\n  \\<->/\n";
 
     #[test]
     fn part1_example_parse() {
-        let map = parse(INPUT);
+        let map = parse(INPUT).unwrap();
 
-        let c: &Cart = map.carts.get(0).expect("No cart found");
+        let c: &Cart = map.carts.first().expect("No cart found");
         assert_eq!(
             c,
             &Cart {
+                id: 0,
                 x: 2,
                 y: 0,
                 direction: Direction::RIGHT,
-                turn_state: TurnState::LEFT,
+                junctions: 0,
             },
             "Incorrect first cart"
         );
@@ -307,7 +68,7 @@ mod tests {
 
     #[test]
     fn part1_example_solve() {
-        let mut map = parse(INPUT).clone();
+        let mut map = parse(INPUT).unwrap();
 
         for _i in 0..13 {
             let c = map.update(false);
@@ -320,10 +81,11 @@ mod tests {
         println!("{}", map);
         assert_eq!(
             Some(Cart {
+                id: 1,
                 x: 7,
                 y: 3,
                 direction: Direction::DOWN,
-                turn_state: TurnState::RIGHT
+                junctions: 2,
             }),
             c,
             "Didn't see expected collision"
@@ -332,7 +94,7 @@ mod tests {
 
     #[test]
     fn part2_example_solve() {
-        let mut map = parse(INPUT2).clone();
+        let mut map = parse(INPUT2).unwrap();
         println!("Original map\n{}", map);
 
         for _i in 0..3 {
@@ -349,38 +111,22 @@ mod tests {
     }
 
     #[test]
-    fn test_check_collision() {
-        let inputs = &vec![
-            (
-                vec![
-                    Cart::new(0, 0, '>' as u8),
-                    Cart::new(0, 1, 'v' as u8),
-                    Cart::new(0, 1, '^' as u8),
-                ],
-                1,
-                Some(2),
-            ),
-            (
-                vec![
-                    Cart::new(0, 0, '>' as u8),
-                    Cart::new(0, 1, 'v' as u8),
-                    Cart::new(0, 1, '^' as u8),
-                ],
-                2,
-                Some(1),
-            ),
-            (
-                vec![
-                    Cart::new(0, 0, '>' as u8),
-                    Cart::new(0, 1, 'v' as u8),
-                    Cart::new(0, 2, '^' as u8),
-                ],
-                1,
-                None,
-            ),
-        ];
-        for (_i, t) in inputs.iter().enumerate() {
-            assert_eq!(check_collision(&t.0, t.1), t.2)
-        }
+    fn part1_with_survivors() {
+        // After the first crash, two carts go round their own loops forever.
+        let map = parse(b"/>-<\\ /->\\ /->\\\n\\---/ \\--/ \\--/\n").unwrap();
+        let cart = solve_part1(&map);
+        assert_eq!((2, 0), (cart.x, cart.y));
+    }
+
+    #[test]
+    fn test_predicted() {
+        assert_eq!(
+            solve_part1(&parse(INPUT).unwrap()),
+            solve_part1_predicted(&parse(INPUT).unwrap())
+        );
+        assert_eq!(
+            solve_part2(&parse(INPUT2).unwrap()),
+            solve_part2_predicted(&parse(INPUT2).unwrap())
+        );
     }
 }
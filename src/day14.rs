@@ -1,26 +1,101 @@
-use aoc_runner_derive::aoc;
+use aoc_runner_derive::{aoc, aoc_generator};
+use std::fmt;
+use std::fmt::Formatter;
 
-#[derive(Clone)]
-struct State {
-    current_recipes: Vec<usize>,
-    scoreboard: Vec<usize>,
+/// The puzzle input is the target number on its own line, optionally followed by
+/// `recipes: <digits>` for the starting scoreboard and `workers: <n>` for the number of elves.
+#[derive(Clone, Debug, PartialEq)]
+struct Puzzle {
+    /// The target's digits, leading zeros and all, for part 2.
+    target: Vec<u8>,
+    /// The target as a number of recipes, for part 1.
+    count: usize,
+    recipes: Vec<u8>,
+    workers: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ParseError {
+    MissingTarget,
+    BadTarget(String),
+    BadRecipes(String),
+    BadWorkers(String),
+    TooManyWorkers { workers: usize, recipes: usize },
+    UnknownLine(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseError::MissingTarget => write!(f, "no target number"),
+            ParseError::BadTarget(s) => write!(f, "bad target number `{}`", s),
+            ParseError::BadRecipes(s) => write!(f, "recipes must be digits, not `{}`", s),
+            ParseError::BadWorkers(s) => write!(f, "bad number of workers `{}`", s),
+            ParseError::TooManyWorkers { workers, recipes } => write!(
+                f,
+                "{} workers need at least as many recipes, not {}",
+                workers, recipes
+            ),
+            ParseError::UnknownLine(s) => write!(f, "unexpected line `{}`", s),
+        }
+    }
 }
 
-fn number_to_digits(num: usize) -> Vec<usize> {
-    if num == 0 {
-        return vec![0];
+impl std::error::Error for ParseError {}
+
+fn digits(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
     }
-    let mut ret = Vec::with_capacity((num as f64).log10() as usize + 1);
-    let mut val = num;
-    while val > 0 {
-        ret.insert(0, val % 10);
-        val /= 10;
+    Some(s.bytes().map(|b| b - b'0').collect())
+}
+
+#[aoc_generator(day14)]
+fn parse_input(input: &str) -> Result<Puzzle, ParseError> {
+    let mut lines = input.lines().map(str::trim).filter(|l| !l.is_empty());
+    let line = lines.next().ok_or(ParseError::MissingTarget)?;
+    let target = digits(line).ok_or_else(|| ParseError::BadTarget(line.to_owned()))?;
+    let count = line
+        .parse()
+        .map_err(|_| ParseError::BadTarget(line.to_owned()))?;
+    let mut puzzle = Puzzle {
+        target,
+        count,
+        recipes: vec![3, 7],
+        workers: 2,
+    };
+    for line in lines {
+        if let Some(value) = line.strip_prefix("recipes:") {
+            let value = value.trim();
+            puzzle.recipes =
+                digits(value).ok_or_else(|| ParseError::BadRecipes(value.to_owned()))?;
+        } else if let Some(value) = line.strip_prefix("workers:") {
+            let value = value.trim();
+            puzzle.workers = match value.parse() {
+                Ok(workers) if workers > 0 => workers,
+                _ => return Err(ParseError::BadWorkers(value.to_owned())),
+            };
+        } else {
+            return Err(ParseError::UnknownLine(line.to_owned()));
+        }
     }
-    ret
+    if puzzle.workers > puzzle.recipes.len() {
+        return Err(ParseError::TooManyWorkers {
+            workers: puzzle.workers,
+            recipes: puzzle.recipes.len(),
+        });
+    }
+    Ok(puzzle)
+}
+
+#[derive(Clone)]
+struct State {
+    current_recipes: Vec<usize>,
+    scoreboard: Vec<u8>,
 }
 
 impl State {
-    fn new(initial: &[usize], num_workers: usize) -> State {
+    fn new(initial: &[u8], num_workers: usize) -> State {
         assert!(initial.len() >= num_workers);
         let mut state = State {
             current_recipes: Vec::new(),
@@ -32,29 +107,108 @@ impl State {
         state
     }
 
-    fn make_recipe(&mut self) {
+    /// Returns the index of the first new recipe.
+    fn make_recipe(&mut self) -> usize {
+        let start = self.scoreboard.len();
         // Get sum of all current recipes
         let score: usize = self
             .current_recipes
             .iter()
-            .fold(0usize, |acc, i| acc + self.scoreboard[*i]);
-        // Add the digits of the sum to the scoreboard
-        self.scoreboard.extend(number_to_digits(score));
+            .fold(0usize, |acc, i| acc + self.scoreboard[*i] as usize);
+        // Add the digits of the sum to the scoreboard, most significant first
+        let mut digits = [0u8; 20];
+        let mut first = digits.len();
+        let mut val = score;
+        loop {
+            first -= 1;
+            digits[first] = (val % 10) as u8;
+            val /= 10;
+            if val == 0 {
+                break;
+            }
+        }
+        self.scoreboard.extend_from_slice(&digits[first..]);
 
         // Advance each player the correct number of recipes
         for i in 0..self.current_recipes.len() {
             self.current_recipes[i] =
-                (self.current_recipes[i] + self.scoreboard[self.current_recipes[i]] + 1)
+                (self.current_recipes[i] + self.scoreboard[self.current_recipes[i]] as usize + 1)
                     % self.scoreboard.len();
         }
+        start
+    }
+
+    /// Make recipes until `pattern` turns up, and return how many recipes come before it.
+    fn find(&mut self, pattern: &[u8]) -> usize {
+        let mut matcher = Matcher::new(pattern);
+        if matcher.is_match() {
+            return 0;
+        }
+        let mut checked = 0;
+        loop {
+            for i in checked..self.scoreboard.len() {
+                if matcher.push(self.scoreboard[i]) {
+                    return i + 1 - pattern.len();
+                }
+            }
+            checked = self.make_recipe();
+        }
+    }
+}
+
+/// Knuth-Morris-Pratt matching of a fixed pattern against digits fed in one at a time.
+struct Matcher {
+    pattern: Vec<u8>,
+    /// For each prefix length, the length of its longest proper prefix that is also a suffix.
+    failure: Vec<usize>,
+    /// How much of the pattern the digits so far end with.
+    matched: usize,
+}
+
+impl Matcher {
+    fn new(pattern: &[u8]) -> Matcher {
+        let mut failure = vec![0; pattern.len() + 1];
+        let mut k = 0;
+        for i in 1..pattern.len() {
+            while k > 0 && pattern[i] != pattern[k] {
+                k = failure[k];
+            }
+            if pattern[i] == pattern[k] {
+                k += 1;
+            }
+            failure[i + 1] = k;
+        }
+        Matcher {
+            pattern: pattern.to_owned(),
+            failure,
+            matched: 0,
+        }
+    }
+
+    fn is_match(&self) -> bool {
+        self.matched == self.pattern.len()
+    }
+
+    /// Feed in the next digit, returning whether the digits so far end with the pattern.
+    fn push(&mut self, digit: u8) -> bool {
+        if self.is_match() {
+            self.matched = self.failure[self.matched];
+        }
+        while self.matched > 0 && self.pattern[self.matched] != digit {
+            self.matched = self.failure[self.matched];
+        }
+        if self.pattern[self.matched] == digit {
+            self.matched += 1;
+        }
+        self.is_match()
     }
 }
 
 #[aoc(day14, part1)]
-pub fn solve_part1(_input: &str) -> String {
-    let mut state = State::new(&[3usize, 7], 2);
+fn solve_part1(input: &Puzzle) -> String {
+    let mut state = State::new(&input.recipes, input.workers);
 
-    let target_scoreboard_num: usize = 681_901;
+    let target_scoreboard_num = input.count;
 
     while state.scoreboard.len() < target_scoreboard_num + 10 {
         state.make_recipe();
@@ -63,40 +217,28 @@ pub fn solve_part1(_input: &str) -> String {
     String::from_utf8(
         state.scoreboard[target_scoreboard_num..target_scoreboard_num + 10]
             .iter()
-            .map(|c| (*c + '0' as usize) as u8)
+            .map(|c| c + b'0')
             .collect(),
     )
     .expect("Invalid UTF8!")
 }
 
-fn find_index_of_slice(match_slice: &[usize]) -> usize {
-    let mut state = State::new(&[3usize, 7], 2);
-
-    let mut i: usize = 0;
-    loop {
-        while state.scoreboard.len() <= i + match_slice.len() {
-            state.make_recipe();
-        }
-        if &state.scoreboard[i..i + match_slice.len()] == match_slice {
-            return i;
-        } else {
-            i += 1;
-        }
-    }
-}
-
 #[aoc(day14, part2)]
-fn solve_part2(_input: &str) -> usize {
-    find_index_of_slice(&[6usize, 8, 1, 9, 0, 1])
+fn solve_part2(input: &Puzzle) -> usize {
+    State::new(&input.recipes, input.workers).find(&input.target)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn find_index_of_slice(match_slice: &[u8]) -> usize {
+        State::new(&[3, 7], 2).find(match_slice)
+    }
+
     #[test]
     fn test_example() {
-        let mut state = State::new(&vec![3usize, 7usize], 2);
+        let mut state = State::new(&[3, 7], 2);
         for _i in 0..14 {
             state.make_recipe();
             for (i, s) in state.scoreboard.iter().enumerate() {
@@ -122,4 +264,84 @@ mod tests {
         assert_eq!(18, find_index_of_slice(&[9, 2, 5, 1, 0]));
         assert_eq!(2018, find_index_of_slice(&[5, 9, 4, 1, 4]));
     }
+
+    #[test]
+    fn test_matcher() {
+        // Partial matches that overlap the start of the pattern.
+        let mut matcher = Matcher::new(&[1, 1, 2, 1, 1, 3]);
+        let digits = [1, 1, 2, 1, 1, 2, 1, 1, 3, 1, 1, 2, 1, 1, 3];
+        let found = digits
+            .iter()
+            .enumerate()
+            .filter(|&(_, &d)| matcher.push(d))
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        assert_eq!(vec![8, 14], found);
+    }
+
+    #[test]
+    fn test_more_workers() {
+        // Compare with a plain scan of the scoreboard.
+        let mut state = State::new(&[9, 4, 6, 1], 3);
+        for _ in 0..2000 {
+            state.make_recipe();
+        }
+        let pattern = state.scoreboard[1500..1506].to_vec();
+        let expected = state
+            .scoreboard
+            .windows(pattern.len())
+            .position(|w| w == &pattern[..])
+            .unwrap();
+        assert_eq!(expected, State::new(&[9, 4, 6, 1], 3).find(&pattern));
+        assert_eq!(2, State::new(&[9, 4, 6, 1], 3).find(&[6, 1]));
+        assert_eq!(0, State::new(&[3, 7], 2).find(&[]));
+    }
+
+    #[test]
+    fn test_parse() {
+        let puzzle = parse_input("01245\n").unwrap();
+        assert_eq!(vec![0, 1, 2, 4, 5], puzzle.target);
+        assert_eq!(1245, puzzle.count);
+        assert_eq!((vec![3, 7], 2), (puzzle.recipes, puzzle.workers));
+
+        let puzzle = parse_input("9\nrecipes: 9461\nworkers: 3\n").unwrap();
+        assert_eq!((vec![9, 4, 6, 1], 3), (puzzle.recipes, puzzle.workers));
+
+        assert_eq!(Err(ParseError::MissingTarget), parse_input("\n"));
+        assert_eq!(
+            Err(ParseError::BadTarget("12a".to_owned())),
+            parse_input("12a")
+        );
+        assert_eq!(
+            Err(ParseError::BadRecipes("3 7".to_owned())),
+            parse_input("9\nrecipes: 3 7")
+        );
+        assert_eq!(
+            Err(ParseError::BadWorkers("0".to_owned())),
+            parse_input("9\nworkers: 0")
+        );
+        assert_eq!(
+            "3 workers need at least as many recipes, not 2",
+            parse_input("9\nworkers: 3").unwrap_err().to_string()
+        );
+        assert_eq!(
+            Err(ParseError::UnknownLine("elves: 2".to_owned())),
+            parse_input("9\nelves: 2")
+        );
+    }
+
+    #[test]
+    fn test_examples() {
+        let part1 = |input| solve_part1(&parse_input(input).unwrap());
+        assert_eq!("5158916779", part1("9"));
+        assert_eq!("0124515891", part1("5"));
+        assert_eq!("9251071085", part1("18"));
+        assert_eq!("5941429882", part1("2018"));
+
+        let part2 = |input| solve_part2(&parse_input(input).unwrap());
+        assert_eq!(9, part2("51589"));
+        assert_eq!(5, part2("01245"));
+        assert_eq!(18, part2("92510"));
+        assert_eq!(2018, part2("59414"));
+    }
 }
@@ -1,7 +1,9 @@
 use aoc_runner_derive::{aoc, aoc_generator};
 
+use crate::elfcode::codec::{self, CodecError};
+use crate::elfcode::{Instr, Machine, OpType, Program, Word};
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::fmt;
 use std::fmt::Error;
 use std::fmt::Formatter;
@@ -38,55 +40,14 @@ eqri (equal register/immediate) sets register C to 1 if register A is equal to v
 eqrr (equal register/register) sets register C to 1 if register A is equal to register B. Otherwise, register C is set to 0.
 */
 
-#[derive(Clone, Debug, PartialEq, Copy, Eq, Hash)]
-enum OpType {
-    ADDR,
-    ADDI,
-    MULR,
-    MULI,
-    BANR,
-    BANI,
-    BORR,
-    BORI,
-    SETR,
-    SETI,
-    GTIR,
-    GTRI,
-    GTRR,
-    EQIR,
-    EQRI,
-    EQRR,
-}
-
-fn get_all_opcodes() -> Vec<OpType> {
-    vec![
-        OpType::ADDR,
-        OpType::ADDI,
-        OpType::MULR,
-        OpType::MULI,
-        OpType::BANR,
-        OpType::BANI,
-        OpType::BORR,
-        OpType::BORI,
-        OpType::SETR,
-        OpType::SETI,
-        OpType::GTIR,
-        OpType::GTRI,
-        OpType::GTRR,
-        OpType::EQIR,
-        OpType::EQRI,
-        OpType::EQRR,
-    ]
-}
-
 #[derive(Debug, Clone, PartialEq)]
 struct Part1TestCase {
-    before: Vec<i32>,
-    after: Vec<i32>,
-    opcode_raw: i32,
-    op_rega: i32,
-    op_regb: i32,
-    op_rego: i32,
+    before: Vec<Word>,
+    after: Vec<Word>,
+    opcode_raw: Word,
+    op_rega: Word,
+    op_regb: Word,
+    op_rego: Word,
 }
 
 impl fmt::Display for Part1TestCase {
@@ -99,31 +60,28 @@ impl fmt::Display for Part1TestCase {
     }
 }
 
-fn apply_opcode(op: OpType, rega: i32, regb: i32, rego: i32, reg: &[i32]) -> Vec<i32> {
-    let mut out: Vec<i32> = Vec::from(reg);
-    out[rego as usize] = match op {
-        OpType::ADDR => out[rega as usize] + out[regb as usize],
-        OpType::ADDI => out[rega as usize] + regb,
-        OpType::MULR => out[rega as usize] * out[regb as usize],
-        OpType::MULI => out[rega as usize] * regb,
-        OpType::BANR => out[rega as usize] & out[regb as usize],
-        OpType::BANI => out[rega as usize] & regb,
-        OpType::BORR => out[rega as usize] | out[regb as usize],
-        OpType::BORI => out[rega as usize] | regb,
-        OpType::SETR => out[rega as usize],
-        OpType::SETI => rega,
-        OpType::GTIR => (rega > out[regb as usize]) as i32,
-        OpType::GTRI => (out[rega as usize] > regb) as i32,
-        OpType::GTRR => (out[rega as usize] > out[regb as usize]) as i32,
-        OpType::EQIR => (rega == out[regb as usize]) as i32,
-        OpType::EQRI => (out[rega as usize] == regb) as i32,
-        OpType::EQRR => (out[rega as usize] == out[regb as usize]) as i32,
-    };
-    out
+impl Part1TestCase {
+    fn instr(&self, op: OpType) -> Instr {
+        Instr::new(op, self.op_rega, self.op_regb, self.op_rego as usize)
+    }
+
+    fn matches(&self, op: OpType) -> bool {
+        let in_range =
+            |r: Word, is_reg: bool| !is_reg || (0..self.before.len() as Word).contains(&r);
+        if !in_range(self.op_rega, op.a_is_reg())
+            || !in_range(self.op_regb, op.b_is_reg())
+            || !in_range(self.op_rego, true)
+        {
+            return false;
+        }
+        let mut out = self.before.clone();
+        self.instr(op).execute(&mut out);
+        out == self.after
+    }
 }
 
 #[aoc_generator(day16)]
-fn parse_input(input: &str) -> (Vec<Part1TestCase>, Vec<Vec<i32>>) {
+fn parse_input(input: &str) -> (Vec<Part1TestCase>, Vec<Vec<Word>>) {
     let test_case: Regex =
         Regex::new(r"Before: \[(\d+), (\d+), (\d+), (\d+)]\n(\d+) (\d+) (\d+) (\d+)\nAfter:  \[(\d+), (\d+), (\d+), (\d+)]").unwrap();
 
@@ -134,10 +92,10 @@ fn parse_input(input: &str) -> (Vec<Part1TestCase>, Vec<Vec<i32>>) {
         let nums = c
             .iter()
             .filter_map(|i| match i {
-                Some(m) => m.as_str().parse::<i32>().ok(),
+                Some(m) => m.as_str().parse::<Word>().ok(),
                 None => None,
             })
-            .collect::<Vec<i32>>();
+            .collect::<Vec<Word>>();
         end = c.get(0).unwrap().end();
         part1_ret.push(Part1TestCase {
             before: nums[0..4].to_vec(),
@@ -149,38 +107,27 @@ fn parse_input(input: &str) -> (Vec<Part1TestCase>, Vec<Vec<i32>>) {
         });
     }
 
-    let mut part2_ret: Vec<Vec<i32>> = Vec::new();
+    let mut part2_ret: Vec<Vec<Word>> = Vec::new();
     for l in input[end..].lines() {
         if l.is_empty() {
             continue;
         }
         part2_ret.push(
             l.split_whitespace()
-                .map(|d| d.parse::<i32>().unwrap())
-                .collect::<Vec<i32>>(),
+                .map(|d| d.parse::<Word>().unwrap())
+                .collect::<Vec<Word>>(),
         );
     }
     (part1_ret, part2_ret)
 }
 
 #[aoc(day16, part1)]
-fn solve_part1(input: &(Vec<Part1TestCase>, Vec<Vec<i32>>)) -> i32 {
+fn solve_part1(input: &(Vec<Part1TestCase>, Vec<Vec<Word>>)) -> i32 {
     let mut total: i32 = 0;
     'test: for test_case in &input.0 {
         let mut count: i32 = 0;
-        for op in get_all_opcodes() {
-            let out = apply_opcode(
-                op,
-                test_case.op_rega,
-                test_case.op_regb,
-                test_case.op_rego,
-                test_case.before.as_slice(),
-            );
-            if out
-                .iter()
-                .zip(test_case.after.iter())
-                .all(|(a, b)| *a == *b)
-            {
+        for &op in OpType::ALL.iter() {
+            if test_case.matches(op) {
                 //println!("{:?} -> {:?} {:?} {:?} {:?} -> {:?} <=> {:?}", test_case.before, op, test_case.op_rega, test_case.op_regb, test_case.op_rego, out, test_case.after);
                 count += 1;
             }
@@ -193,88 +140,233 @@ fn solve_part1(input: &(Vec<Part1TestCase>, Vec<Vec<i32>>)) -> i32 {
     total
 }
 
-fn assign_code(
-    constraints: &[HashSet<OpType>],
-    assigned_list: &[Option<OpType>],
-    unassigned_list: &[OpType],
-) -> Option<Vec<OpType>> {
-    //println!("Assigned: {:?}\nUnassigned:{:?}", assigned_list, unassigned_list);
-    if unassigned_list.is_empty() {
-        return Some(
-            assigned_list
-                .iter()
-                .map(|o| o.unwrap())
-                .collect::<Vec<OpType>>(),
-        );
-    }
-    let op = unassigned_list[0];
-    let mut attempt = assigned_list.to_vec();
-    for code in 0..16usize {
-        if attempt[code].is_none() && !constraints[code].contains(&op) {
-            attempt[code] = Some(op);
-            if let Some(assignment) =
-                assign_code(constraints, &attempt.as_slice(), &unassigned_list[1..])
-            {
-                return Some(assignment);
+/// Why an operation was ruled out for an opcode number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reason {
+    /// The operation doesn't reproduce this sample.
+    Sample(usize),
+    /// The operation is the only one left for this other opcode.
+    TakenBy(usize),
+    /// This other operation can't be any other opcode.
+    Forced(OpType),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum InferenceError {
+    /// The sample behaves like no operation at all.
+    Unmatched { sample: usize },
+    /// Every operation is ruled out for the opcode; the samples are those responsible.
+    NoOperation { code: usize, samples: Vec<usize> },
+    /// Every opcode is ruled out for the operation.
+    NoOpcode { op: OpType, samples: Vec<usize> },
+    /// Each opcode has candidates left, but no one-to-one mapping uses them.
+    NoMapping { samples: Vec<usize> },
+    /// The candidates left for each opcode, and two different mappings that fit every sample.
+    Ambiguous {
+        candidates: Vec<Vec<OpType>>,
+        mappings: [Vec<OpType>; 2],
+    },
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            InferenceError::Unmatched { sample } => {
+                write!(f, "Sample {} matches no operation", sample)
+            }
+            InferenceError::NoOperation { code, samples } => write!(
+                f,
+                "No operation fits opcode {} (samples {:?})",
+                code, samples
+            ),
+            InferenceError::NoOpcode { op, samples } => {
+                write!(f, "No opcode can be {} (samples {:?})", op, samples)
+            }
+            InferenceError::NoMapping { samples } => {
+                write!(f, "No consistent opcode mapping (samples {:?})", samples)
+            }
+            InferenceError::Ambiguous {
+                candidates,
+                mappings,
+            } => {
+                write!(f, "Opcode mapping is ambiguous:")?;
+                for (code, ops) in candidates.iter().enumerate().filter(|(_, o)| o.len() > 1) {
+                    write!(f, " {} is one of {:?};", code, ops)?;
+                }
+                write!(f, " both {:?} and {:?} fit", mappings[0], mappings[1])
             }
-            attempt[code] = None;
         }
     }
-    None
 }
 
-#[aoc(day16, part2)]
-fn solve_part2(input: &(Vec<Part1TestCase>, Vec<Vec<i32>>)) -> i32 {
-    // Figure out a mapping from opcode number to operation type
-    let mut incompatible_ops: Vec<HashSet<OpType>> = vec![HashSet::new(); 16];
-
-    for test_case in &input.0 {
-        for op in get_all_opcodes() {
-            let out = apply_opcode(
-                op,
-                test_case.op_rega,
-                test_case.op_regb,
-                test_case.op_rego,
-                test_case.before.as_slice(),
-            );
-            if out
-                .iter()
-                .zip(test_case.after.iter())
-                .any(|(a, b)| *a != *b)
-            {
-                incompatible_ops[test_case.opcode_raw as usize].insert(op);
+impl std::error::Error for InferenceError {}
+
+struct Solver {
+    /// `ruled_out[code][op]` says why `op` can't be `code`, or is None while it still might be.
+    ruled_out: Vec<Vec<Option<Reason>>>,
+}
+
+impl Solver {
+    fn new(samples: &[Part1TestCase]) -> Result<Solver, InferenceError> {
+        let mut ruled_out = vec![vec![None; OpType::ALL.len()]; OpType::ALL.len()];
+        for (i, sample) in samples.iter().enumerate() {
+            let code = sample.opcode_raw as usize;
+            if code >= ruled_out.len() || !OpType::ALL.iter().any(|&op| sample.matches(op)) {
+                return Err(InferenceError::Unmatched { sample: i });
+            }
+            for &op in OpType::ALL.iter() {
+                if ruled_out[code][op as usize].is_none() && !sample.matches(op) {
+                    ruled_out[code][op as usize] = Some(Reason::Sample(i));
+                }
             }
         }
+        Ok(Solver { ruled_out })
     }
-    println!("{:#?}", incompatible_ops);
-
-    let code_map = match assign_code(
-        incompatible_ops.as_slice(),
-        &[None; 16],
-        get_all_opcodes().as_slice(),
-    ) {
-        Some(m) => m,
-        None => panic!("Unable to build code map"),
-    };
-    println!("{:#?}", code_map);
 
-    // Run the program
-    let mut registers: Vec<i32> = vec![0i32; 4];
-    for cmd in &input.1 {
-        registers = apply_opcode(
-            code_map[cmd[0] as usize],
-            cmd[1],
-            cmd[2],
-            cmd[3],
-            &registers,
-        );
+    fn candidates(&self, code: usize) -> Vec<OpType> {
+        OpType::ALL
+            .iter()
+            .cloned()
+            .filter(|&op| self.ruled_out[code][op as usize].is_none())
+            .collect()
+    }
+
+    fn codes(&self, op: OpType) -> Vec<usize> {
+        (0..self.ruled_out.len())
+            .filter(|&code| self.ruled_out[code][op as usize].is_none())
+            .collect()
+    }
+
+    /// Rule out candidates until neither an opcode with one operation left nor an operation
+    /// with one opcode left tells us anything new.
+    fn propagate(&mut self) -> Result<(), InferenceError> {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for code in 0..self.ruled_out.len() {
+                match self.candidates(code)[..] {
+                    [] => {
+                        let samples = self.explain((0..16).map(|op| (code, op)));
+                        return Err(InferenceError::NoOperation { code, samples });
+                    }
+                    [op] => {
+                        for other in self.codes(op).into_iter().filter(|&c| c != code) {
+                            self.ruled_out[other][op as usize] = Some(Reason::TakenBy(code));
+                            changed = true;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            for &op in OpType::ALL.iter() {
+                match self.codes(op)[..] {
+                    [] => {
+                        let samples = self.explain((0..16).map(|code| (code, op as usize)));
+                        return Err(InferenceError::NoOpcode { op, samples });
+                    }
+                    [code] => {
+                        for other in self.candidates(code).into_iter().filter(|&o| o != op) {
+                            self.ruled_out[code][other as usize] = Some(Reason::Forced(op));
+                            changed = true;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The samples that, through propagation, ruled out each (code, op) pair given.
+    fn explain<I: Iterator<Item = (usize, usize)>>(&self, facts: I) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut samples = BTreeSet::new();
+        let mut todo: Vec<(usize, usize)> = facts.collect();
+        while let Some((code, op)) = todo.pop() {
+            if !seen.insert((code, op)) {
+                continue;
+            }
+            match self.ruled_out[code][op] {
+                Some(Reason::Sample(i)) => {
+                    samples.insert(i);
+                }
+                Some(Reason::TakenBy(other)) => {
+                    todo.extend((0..16).filter(|&o| o != op).map(|o| (other, o)));
+                }
+                Some(Reason::Forced(forced)) => {
+                    let forced = forced as usize;
+                    todo.extend((0..16).filter(|&c| c != code).map(|c| (c, forced)));
+                }
+                None => (),
+            }
+        }
+        samples.into_iter().collect()
+    }
+
+    /// Find up to `limit` one-to-one mappings using the remaining candidates.
+    fn search(&self, mapping: &mut Vec<OpType>, found: &mut Vec<Vec<OpType>>, limit: usize) {
+        if found.len() >= limit {
+            return;
+        }
+        let code = mapping.len();
+        if code == self.ruled_out.len() {
+            found.push(mapping.clone());
+            return;
+        }
+        for op in self.candidates(code) {
+            if !mapping.contains(&op) {
+                mapping.push(op);
+                self.search(mapping, found, limit);
+                mapping.pop();
+            }
+        }
     }
-    registers[0]
+}
+
+/// Work out which operation each opcode number stands for, returning the operations indexed
+/// by opcode.
+fn infer_opcodes(samples: &[Part1TestCase]) -> Result<Vec<OpType>, InferenceError> {
+    let mut solver = Solver::new(samples)?;
+    solver.propagate()?;
+
+    let mut found = Vec::new();
+    solver.search(&mut Vec::new(), &mut found, 2);
+    match found.len() {
+        0 => Err(InferenceError::NoMapping {
+            samples: solver.explain((0..16).flat_map(|code| (0..16).map(move |op| (code, op)))),
+        }),
+        1 => Ok(found.pop().unwrap()),
+        _ => Err(InferenceError::Ambiguous {
+            candidates: (0..16).map(|code| solver.candidates(code)).collect(),
+            mappings: [found.swap_remove(0), found.swap_remove(0)],
+        }),
+    }
+}
+
+#[aoc(day16, part2)]
+fn solve_part2(
+    input: &(Vec<Part1TestCase>, Vec<Vec<Word>>),
+) -> Result<Word, Box<dyn std::error::Error>> {
+    let code_map = infer_opcodes(&input.0)?;
+
+    // Run the program
+    let program = Program {
+        ip_reg: None,
+        instrs: input
+            .1
+            .iter()
+            .map(|cmd| codec::decode([cmd[0], cmd[1], cmd[2], cmd[3]], &code_map))
+            .collect::<Result<Vec<Instr>, CodecError>>()?,
+    };
+    let mut machine = Machine::new(program, 4);
+    machine.run();
+    Ok(machine.reg[0])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::elfcode::samples::SampleGenerator;
 
     #[test]
     fn test_parse() {
@@ -302,8 +394,158 @@ mod tests {
 
     #[test]
     fn test_solve_part1() {
-        let test_case: (Vec<Part1TestCase>, Vec<Vec<i32>>) =
+        let test_case: (Vec<Part1TestCase>, Vec<Vec<Word>>) =
             parse_input("Before: [3, 2, 1, 1]\n9 2 1 2\nAfter:  [3, 2, 2, 1]");
         assert_eq!(1, solve_part1(&test_case));
     }
+
+    fn sample(code: usize, op: OpType, before: [Word; 4], args: [Word; 3]) -> Part1TestCase {
+        let mut after = before.to_vec();
+        Instr::new(op, args[0], args[1], args[2] as usize).execute(&mut after);
+        Part1TestCase {
+            before: before.to_vec(),
+            after,
+            opcode_raw: code as Word,
+            op_rega: args[0],
+            op_regb: args[1],
+            op_rego: args[2],
+        }
+    }
+
+    // Opcode `code` is `OpType::ALL[(code * 5) % 16]`.
+    fn get_test_samples() -> Vec<Part1TestCase> {
+        let cases = [
+            ([3, 2, 1, 1], [2, 1, 2]),
+            ([0, 5, 7, 2], [1, 3, 0]),
+            ([9, 1, 4, 6], [3, 0, 3]),
+            ([2, 2, 8, 3], [0, 1, 1]),
+            ([12, 3, 3, 0], [2, 3, 3]),
+        ];
+        (0..16)
+            .flat_map(|code| {
+                cases.iter().map(move |&(before, args)| {
+                    sample(code, OpType::ALL[(code * 5) % 16], before, args)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_infer_opcodes() {
+        assert_eq!(
+            Ok((0..16).map(|code| OpType::ALL[(code * 5) % 16]).collect()),
+            infer_opcodes(&get_test_samples())
+        );
+    }
+
+    #[test]
+    fn test_infer_ambiguous() {
+        let samples = parse_input("Before: [3, 2, 1, 1]\n9 2 1 2\nAfter:  [3, 2, 2, 1]").0;
+        match infer_opcodes(&samples) {
+            Err(InferenceError::Ambiguous {
+                candidates,
+                mappings,
+            }) => {
+                assert_eq!(
+                    vec![OpType::Addi, OpType::Mulr, OpType::Seti],
+                    candidates[9]
+                );
+                assert_ne!(mappings[0], mappings[1]);
+            }
+            other => panic!("Expected ambiguity, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_infer_contradiction() {
+        let mut samples = get_test_samples();
+        samples.push(sample(3, OpType::Addr, [3, 2, 1, 1], [2, 1, 2]));
+        match infer_opcodes(&samples) {
+            Err(InferenceError::NoOperation {
+                samples: culprits, ..
+            })
+            | Err(InferenceError::NoOpcode {
+                samples: culprits, ..
+            }) => {
+                assert!(culprits.contains(&(samples.len() - 1)));
+            }
+            other => panic!("Expected a contradiction, got {:?}", other),
+        }
+
+        let mut unmatched = sample(0, OpType::Seti, [0, 0, 0, 0], [0, 0, 0]);
+        unmatched.after = vec![5, 5, 5, 5];
+        samples.push(unmatched);
+        assert_eq!(
+            Err(InferenceError::Unmatched {
+                sample: samples.len() - 1
+            }),
+            infer_opcodes(&samples)
+        );
+    }
+
+    #[test]
+    fn test_inferred_map_codec() {
+        let mut gen = SampleGenerator::new(1);
+        let map = infer_opcodes(&parse_input(&gen.input(800, 0)).0).unwrap();
+        let program = Program::parse("#ip 0\nseti 5 0 1\naddi 0 1 0\nmulr 1 1 0");
+        let numeric = codec::to_numeric(&program, &map).unwrap();
+        assert_eq!(Ok(program), codec::from_numeric(&numeric, &gen.mapping));
+    }
+
+    // Check inference against a generated input for each seed.  With few samples the mapping
+    // may be ambiguous, but the true mapping must always remain a candidate; any other error is
+    // a bug, since the samples come from the true mapping.  Returns how many seeds were solved
+    // outright and how many were ambiguous.
+    fn fuzz(seeds: std::ops::Range<u64>, count: usize) -> (usize, usize) {
+        let mut solved = 0;
+        let mut ambiguous = 0;
+        for seed in seeds {
+            let mut gen = SampleGenerator::new(seed);
+            let input = parse_input(&gen.input(count, 10));
+            assert_eq!(count, input.0.len());
+            assert!(solve_part1(&input) as usize <= count);
+            match infer_opcodes(&input.0) {
+                Ok(mapping) => {
+                    assert_eq!(gen.mapping, mapping, "seed {}", seed);
+                    let mut machine = Machine::new(
+                        Program {
+                            ip_reg: None,
+                            instrs: input
+                                .1
+                                .iter()
+                                .map(|c| {
+                                    Instr::new(mapping[c[0] as usize], c[1], c[2], c[3] as usize)
+                                })
+                                .collect(),
+                        },
+                        4,
+                    );
+                    machine.run();
+                    assert_eq!(machine.reg[0], solve_part2(&input).unwrap());
+                    solved += 1;
+                }
+                Err(InferenceError::Ambiguous { candidates, .. }) => {
+                    for (code, &op) in gen.mapping.iter().enumerate() {
+                        assert!(candidates[code].contains(&op), "seed {}", seed);
+                    }
+                    ambiguous += 1;
+                }
+                Err(e) => panic!("seed {}: {}", seed, e),
+            }
+        }
+        (solved, ambiguous)
+    }
+
+    #[test]
+    fn test_fuzz() {
+        assert_eq!((20, 0), fuzz(0..20, 800));
+        fuzz(20..40, 40);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_fuzz_long() {
+        assert_eq!((2000, 0), fuzz(0..2000, 800));
+        fuzz(2000..4000, 40);
+    }
 }
@@ -1,4 +1,4 @@
-use aoc_runner_derive::aoc;
+use aoc_runner_derive::{aoc, aoc_generator};
 //use std::cmp::min;
 use std::cmp::max;
 use std::collections::VecDeque;
@@ -9,8 +9,55 @@ use std::fmt::Write;
 //use fnv::FnvHashSet;
 //use std::collections::HashSet;
 
-const DEPTH: usize = 11820;
-const TARGET: (usize, usize) = (7, 782);
+/// The puzzle input: `depth: N` and `target: X,Y` lines.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cave {
+    pub depth: usize,
+    pub target: (usize, usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    Missing(&'static str),
+    BadValue(String),
+    UnknownLine(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            ParseError::Missing(key) => write!(f, "missing `{}:` line", key),
+            ParseError::BadValue(line) => write!(f, "bad value in `{}`", line),
+            ParseError::UnknownLine(line) => write!(f, "unexpected line `{}`", line),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[aoc_generator(day22)]
+pub fn parse_input(input: &str) -> Result<Cave, ParseError> {
+    let mut depth = None;
+    let mut target = None;
+    for line in input.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let bad = || ParseError::BadValue(line.to_owned());
+        if let Some(value) = line.strip_prefix("depth:") {
+            depth = Some(value.trim().parse().map_err(|_| bad())?);
+        } else if let Some(value) = line.strip_prefix("target:") {
+            let mut coords = value.split(',').map(|c| c.trim().parse::<usize>());
+            target = match (coords.next(), coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y)), None) => Some((x, y)),
+                _ => return Err(bad()),
+            };
+        } else {
+            return Err(ParseError::UnknownLine(line.to_owned()));
+        }
+    }
+    Ok(Cave {
+        depth: depth.ok_or(ParseError::Missing("depth"))?,
+        target: target.ok_or(ParseError::Missing("target"))?,
+    })
+}
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 struct MapCell(usize);
@@ -314,14 +361,14 @@ impl Map {
 }
 
 #[aoc(day22, part1)]
-fn solve_part1(_input: &str) -> usize {
-    let map = Map::new(DEPTH, TARGET, TARGET);
+fn solve_part1(input: &Cave) -> usize {
+    let map = Map::new(input.depth, input.target, input.target);
     map.get_risk_level()
 }
 
 #[aoc(day22, part2, orig)]
-fn solve_part2(_input: &str) -> usize {
-    let mut map = Map::new(DEPTH, TARGET, TARGET);
+fn solve_part2(input: &Cave) -> usize {
+    let mut map = Map::new(input.depth, input.target, input.target);
     map.find_shortest_time()
 }
 
@@ -329,6 +376,36 @@ fn solve_part2(_input: &str) -> usize {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(Cave {
+                depth: 510,
+                target: (10, 10)
+            }),
+            parse_input("depth: 510\ntarget: 10,10\n")
+        );
+        assert_eq!(
+            Err(ParseError::BadValue("target: 10".to_owned())),
+            parse_input("depth: 510\ntarget: 10")
+        );
+        assert_eq!(
+            Err(ParseError::Missing("depth")),
+            parse_input("target: 10,10")
+        );
+        assert_eq!(
+            "unexpected line `width: 3`",
+            parse_input("width: 3").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_example_input() {
+        let cave = parse_input("depth: 510\ntarget: 10,10\n").unwrap();
+        assert_eq!(114, solve_part1(&cave));
+        assert_eq!(45, solve_part2(&cave));
+    }
+
     #[test]
     fn test_part1_generate_map() {
         let map = Map::new(510, (10, 10), (15, 15));
@@ -1,12 +1,11 @@
+// The generator is shared with day22.rs, which parses the depth and target.
+use crate::day22::{parse_input, Cave};
 use aoc_runner_derive::aoc;
 use num_traits::abs;
 use std::cmp::max;
 use std::cmp::Ordering;
 use std::collections::binary_heap::BinaryHeap;
 
-const DEPTH: usize = 11820;
-const TARGET: (usize, usize) = (7, 782);
-
 type Equipment = usize;
 
 // This needs to be 1 because we want equipment type to be incompatible only
@@ -278,12 +277,9 @@ impl Map {
 }
 
 #[aoc(day22, part2, reimpl)]
-fn solve_part2_reimpl(_input: &str) -> usize {
-    let mut map = Map::new(
-        DEPTH,
-        TARGET,
-        (max(TARGET.0, TARGET.1), max(TARGET.0, TARGET.1)),
-    );
+fn solve_part2_reimpl(input: &Cave) -> usize {
+    let (x, y) = input.target;
+    let mut map = Map::new(input.depth, input.target, (max(x, y), max(x, y)));
     map.find_shortest_time()
 }
 
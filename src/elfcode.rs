@@ -0,0 +1,613 @@
+// Shared Elfcode virtual machine used by days 16, 19 and 21.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+pub mod analysis;
+pub mod asm;
+pub mod cfg;
+pub mod codec;
+pub mod compile;
+pub mod debug;
+pub mod decompile;
+pub mod halting;
+pub mod optimize;
+pub mod profile;
+pub mod samples;
+pub mod symbolic;
+pub mod trace;
+pub mod width;
+
+use analysis::Range;
+use optimize::Native;
+use profile::Profile;
+use std::collections::HashMap;
+use width::{Overflow, Register, Trap, TrapKind};
+
+pub type Word = i64;
+
+#[derive(Clone, Debug, PartialEq, Copy, Eq, Hash, PartialOrd, Ord)]
+pub enum OpType {
+    Addr,
+    Addi,
+    Mulr,
+    Muli,
+    Banr,
+    Bani,
+    Borr,
+    Bori,
+    Setr,
+    Seti,
+    Gtir,
+    Gtri,
+    Gtrr,
+    Eqir,
+    Eqri,
+    Eqrr,
+}
+
+impl OpType {
+    pub const ALL: [OpType; 16] = [
+        OpType::Addr,
+        OpType::Addi,
+        OpType::Mulr,
+        OpType::Muli,
+        OpType::Banr,
+        OpType::Bani,
+        OpType::Borr,
+        OpType::Bori,
+        OpType::Setr,
+        OpType::Seti,
+        OpType::Gtir,
+        OpType::Gtri,
+        OpType::Gtrr,
+        OpType::Eqir,
+        OpType::Eqri,
+        OpType::Eqrr,
+    ];
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            OpType::Addr => "addr",
+            OpType::Addi => "addi",
+            OpType::Mulr => "mulr",
+            OpType::Muli => "muli",
+            OpType::Banr => "banr",
+            OpType::Bani => "bani",
+            OpType::Borr => "borr",
+            OpType::Bori => "bori",
+            OpType::Setr => "setr",
+            OpType::Seti => "seti",
+            OpType::Gtir => "gtir",
+            OpType::Gtri => "gtri",
+            OpType::Gtrr => "gtrr",
+            OpType::Eqir => "eqir",
+            OpType::Eqri => "eqri",
+            OpType::Eqrr => "eqrr",
+        }
+    }
+
+    /// Whether input A names a register (as opposed to an immediate value).
+    pub fn a_is_reg(self) -> bool {
+        !matches!(self, OpType::Seti | OpType::Gtir | OpType::Eqir)
+    }
+
+    /// Whether input B names a register.  Input B is ignored entirely by setr and seti.
+    pub fn b_is_reg(self) -> bool {
+        matches!(
+            self,
+            OpType::Addr
+                | OpType::Mulr
+                | OpType::Banr
+                | OpType::Borr
+                | OpType::Gtir
+                | OpType::Gtrr
+                | OpType::Eqir
+                | OpType::Eqrr
+        )
+    }
+
+    /// Compute the value this operation would store into register C, wrapping on overflow.
+    /// Panics if an operand doesn't exist or doesn't fit in `R`; see `try_eval`.
+    pub fn eval<R: Register>(self, a: Word, b: Word, reg: &[R]) -> R {
+        self.try_eval(a, b, reg, Overflow::Wrapping)
+            .unwrap_or_else(|kind| panic!("{} {} {}: {:?}", self, a, b, kind))
+    }
+
+    /// Compute the value this operation would store into register C, with the given overflow
+    /// behaviour.
+    pub fn try_eval<R: Register>(
+        self,
+        a: Word,
+        b: Word,
+        reg: &[R],
+        overflow: Overflow,
+    ) -> Result<R, TrapKind<R>> {
+        let operand = |value: Word, is_reg: bool| {
+            if is_reg {
+                reg.get(value as usize)
+                    .cloned()
+                    .filter(|_| value >= 0)
+                    .ok_or(TrapKind::Register(value))
+            } else {
+                R::from_word(value).ok_or(TrapKind::Immediate(value))
+            }
+        };
+        let a = operand(a, self.a_is_reg())?;
+        if let OpType::Setr | OpType::Seti = self {
+            return Ok(a);
+        }
+        let b = operand(b, self.b_is_reg())?;
+        let flag = |set: bool| if set { R::ONE } else { R::ZERO };
+        let overflowed = || TrapKind::Overflow { a, b };
+        match self {
+            OpType::Addr | OpType::Addi => a.add(b, overflow).ok_or_else(overflowed),
+            OpType::Mulr | OpType::Muli => a.mul(b, overflow).ok_or_else(overflowed),
+            OpType::Banr | OpType::Bani => Ok(a.and(b)),
+            OpType::Borr | OpType::Bori => Ok(a.or(b)),
+            OpType::Gtir | OpType::Gtri | OpType::Gtrr => Ok(flag(a > b)),
+            OpType::Eqir | OpType::Eqri | OpType::Eqrr => Ok(flag(a == b)),
+            OpType::Setr | OpType::Seti => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for OpType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+impl FromStr for OpType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OpType, String> {
+        OpType::ALL
+            .iter()
+            .find(|op| op.mnemonic() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown opcode {}", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instr {
+    pub op: OpType,
+    pub a: Word,
+    pub b: Word,
+    pub c: usize,
+}
+
+impl Instr {
+    pub fn new(op: OpType, a: Word, b: Word, c: usize) -> Instr {
+        Instr { op, a, b, c }
+    }
+
+    pub fn execute<R: Register>(&self, reg: &mut [R]) {
+        reg[self.c] = self.op.eval(self.a, self.b, reg);
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.op, self.a, self.b, self.c)
+    }
+}
+
+/// A list of instructions, optionally with the instruction pointer bound to a register.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub ip_reg: Option<usize>,
+    pub instrs: Vec<Instr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnknownOpcode,
+    BadNumber,
+    MissingOperand,
+    UnexpectedToken,
+    /// A register operand is negative.
+    BadRegister,
+    /// The register doesn't exist in a machine with this many registers.
+    RegisterOutOfRange(usize),
+}
+
+/// Where and why a program failed to parse.  Lines and columns count from 1; for a missing
+/// operand the column is just past the end of the line and the token is empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match self.kind {
+            ParseErrorKind::UnknownOpcode => write!(f, "unknown opcode `{}`", self.token),
+            ParseErrorKind::BadNumber => write!(f, "expected a number, got `{}`", self.token),
+            ParseErrorKind::MissingOperand => write!(f, "missing operand"),
+            ParseErrorKind::UnexpectedToken => write!(f, "unexpected `{}`", self.token),
+            ParseErrorKind::BadRegister => write!(f, "bad register `{}`", self.token),
+            ParseErrorKind::RegisterOutOfRange(num_regs) => write!(
+                f,
+                "register {} out of range for {} registers",
+                self.token, num_regs
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    /// Parse without checking register numbers against any particular machine.
+    fn from_str(input: &str) -> Result<Program, ParseError> {
+        parse_program(input, None)
+    }
+}
+
+/// The words of a line, each with the column it starts at.
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace()
+        .map(move |w| (w.as_ptr() as usize - line.as_ptr() as usize + 1, w))
+}
+
+fn parse_program(input: &str, num_regs: Option<usize>) -> Result<Program, ParseError> {
+    let mut ret = Program::default();
+    for (index, line) in input.lines().enumerate() {
+        let error = |(column, token): (usize, &str), kind| ParseError {
+            line: index + 1,
+            column,
+            token: token.to_string(),
+            kind,
+        };
+        let mut words = words(line);
+        let first = match words.next() {
+            Some(first) => first,
+            None => continue,
+        };
+        let mut operand = |is_reg: bool| {
+            let word = words
+                .next()
+                .ok_or_else(|| error((line.len() + 1, ""), ParseErrorKind::MissingOperand))?;
+            let value = word
+                .1
+                .parse::<Word>()
+                .map_err(|_| error(word, ParseErrorKind::BadNumber))?;
+            match num_regs {
+                Some(n) if is_reg && (value < 0 || value as usize >= n) => {
+                    Err(error(word, ParseErrorKind::RegisterOutOfRange(n)))
+                }
+                _ => Ok((value, word)),
+            }
+        };
+        // The ip register and an instruction's output must at least be valid indices.
+        let index = |(value, word): (Word, _)| {
+            if value < 0 {
+                Err(error(word, ParseErrorKind::BadRegister))
+            } else {
+                Ok(value as usize)
+            }
+        };
+        if first.1 == "#ip" {
+            ret.ip_reg = Some(index(operand(true)?)?);
+        } else {
+            let op = first
+                .1
+                .parse::<OpType>()
+                .map_err(|_| error(first, ParseErrorKind::UnknownOpcode))?;
+            ret.instrs.push(Instr {
+                op,
+                a: operand(op.a_is_reg())?.0,
+                b: operand(op.b_is_reg())?.0,
+                c: index(operand(true)?)?,
+            });
+        }
+        if let Some(extra) = words.next() {
+            return Err(error(extra, ParseErrorKind::UnexpectedToken));
+        }
+    }
+    Ok(ret)
+}
+
+impl Program {
+    /// Parse the `#ip N` header and `opcode a b c` lines used by days 19 and 21.  Panics on
+    /// malformed input; see `parse_checked`.
+    pub fn parse(input: &str) -> Program {
+        input.parse().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Parse a program for a machine with `num_regs` registers, rejecting any register
+    /// operand the machine doesn't have.
+    pub fn parse_checked(input: &str, num_regs: usize) -> Result<Program, ParseError> {
+        parse_program(input, Some(num_regs))
+    }
+
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(ip_reg) = self.ip_reg {
+            writeln!(f, "#ip {}", ip_reg)?;
+        }
+        for instr in &self.instrs {
+            writeln!(f, "{}", instr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Executes a program against a register file of configurable width, with registers of type
+/// `R`.  Only `Word` machines can be optimized or profiled.
+#[derive(Clone, Debug)]
+pub struct Machine<R = Word> {
+    pub ip: usize,
+    pub reg: Vec<R>,
+    pub program: Program,
+    /// What happens when addition or multiplication overflows `R`.
+    pub overflow: Overflow,
+    /// Native replacements for recognised idioms, keyed by the instruction they start at.
+    pub natives: HashMap<usize, Native>,
+    pub profile: Option<Profile>,
+}
+
+impl<R: Register> Machine<R> {
+    pub fn with_overflow(program: Program, num_regs: usize, overflow: Overflow) -> Machine<R> {
+        Machine {
+            ip: 0,
+            reg: vec![R::ZERO; num_regs],
+            program,
+            overflow,
+            natives: HashMap::new(),
+            profile: None,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.ip >= self.program.len()
+    }
+
+    /// Returns the instruction that the next call to `step` will execute.
+    pub fn current(&self) -> Option<&Instr> {
+        self.program.instrs.get(self.ip)
+    }
+
+    /// Execute a single instruction, without natives or profiling.  Returns Ok(false) if the
+    /// instruction pointer is outside the program; on a trap, nothing has changed and the ip
+    /// is left at the instruction.
+    pub fn try_step(&mut self) -> Result<bool, Trap<R>> {
+        let pc = self.ip;
+        let instr = match self.program.instrs.get(pc) {
+            Some(&instr) => instr,
+            None => return Ok(false),
+        };
+        let trap = |kind| Trap { pc, instr, kind };
+        let ip_reg = self.program.ip_reg;
+        let mut saved = None;
+        if let Some(r) = ip_reg {
+            if r >= self.reg.len() {
+                return Err(trap(TrapKind::Register(r as Word)));
+            }
+            let ip = R::from_index(pc).ok_or_else(|| trap(TrapKind::Immediate(pc as Word)))?;
+            saved = Some(self.reg[r]);
+            self.reg[r] = ip;
+        }
+        let value = match instr
+            .op
+            .try_eval(instr.a, instr.b, &self.reg, self.overflow)
+        {
+            Ok(value) if instr.c < self.reg.len() => value,
+            result => {
+                if let (Some(r), Some(saved)) = (ip_reg, saved) {
+                    self.reg[r] = saved;
+                }
+                return Err(trap(
+                    result.err().unwrap_or(TrapKind::Register(instr.c as Word)),
+                ));
+            }
+        };
+        self.reg[instr.c] = value;
+        self.ip = match ip_reg {
+            // An ip that doesn't fit is as far outside the program as any other.
+            Some(r) => self.reg[r]
+                .add(R::ONE, Overflow::Checked)
+                .and_then(R::to_index)
+                .unwrap_or(usize::MAX),
+            None => pc + 1,
+        };
+        Ok(true)
+    }
+
+    /// Run until the program halts or traps, returning the number of instructions executed.
+    pub fn try_run(&mut self) -> Result<u64, Trap<R>> {
+        let mut steps = 0;
+        while self.try_step()? {
+            steps += 1;
+        }
+        Ok(steps)
+    }
+}
+
+impl Machine {
+    pub fn new(program: Program, num_regs: usize) -> Machine {
+        Machine::with_overflow(program, num_regs, Overflow::Wrapping)
+    }
+
+    /// Start recording a `Profile` of subsequent execution.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new(&self.program, self.reg.len()));
+    }
+
+    /// Replace recognised loop idioms with native code, for running on from the current state.
+    /// Natives assume wrapping arithmetic, so other overflow behaviours get none.
+    pub fn optimize(&mut self) {
+        if self.overflow != Overflow::Wrapping {
+            self.natives.clear();
+            return;
+        }
+        let entry = self
+            .reg
+            .iter()
+            .map(|&value| Range::exactly(value))
+            .collect::<Vec<Range>>();
+        self.natives = optimize::optimize(&self.program, self.ip, &entry);
+    }
+
+    /// Execute a single instruction.  Returns false (without changing any state) if the
+    /// instruction pointer is outside the program.  Panics on a trap.
+    pub fn step(&mut self) -> bool {
+        let pc = self.ip;
+        if pc >= self.program.len() {
+            return false;
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_entry(pc, &self.reg);
+        }
+        let native = match self.natives.get(&pc) {
+            Some(native) => {
+                self.ip = native.execute(&mut self.reg);
+                true
+            }
+            None => {
+                if let Err(trap) = self.try_step() {
+                    panic!("{}", trap);
+                }
+                false
+            }
+        };
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_exit(pc, self.ip, native);
+        }
+        true
+    }
+
+    /// Run until the program halts, returning the number of instructions executed.
+    pub fn run(&mut self) -> usize {
+        let mut steps = 0;
+        while self.step() {
+            steps += 1;
+        }
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The day 19 example, shared with the submodules' tests.
+    pub(crate) fn get_test_program() -> Program {
+        Program::parse(
+            "#ip 0
+            seti 5 0 1
+            seti 6 0 2
+            addi 0 1 0
+            addr 1 2 3
+            setr 1 0 0
+            seti 8 0 4
+            seti 9 0 5",
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let program = get_test_program();
+        assert_eq!(Some(0), program.ip_reg);
+        assert_eq!(7, program.len());
+        assert_eq!(Instr::new(OpType::Addi, 0, 1, 0), program.instrs[2]);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let program = get_test_program();
+        assert_eq!(program, Program::parse(&program.to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |input: &str| match input.parse::<Program>() {
+            Err(e) => (e.line, e.column, e.token, e.kind),
+            Ok(program) => panic!("Expected an error, got {:?}", program),
+        };
+        assert_eq!(
+            (2, 1, "addz".to_string(), ParseErrorKind::UnknownOpcode),
+            error("#ip 0\naddz 1 2 3")
+        );
+        assert_eq!(
+            (1, 8, "x".to_string(), ParseErrorKind::BadNumber),
+            error("seti 1 x 3")
+        );
+        assert_eq!(
+            (1, 9, "".to_string(), ParseErrorKind::MissingOperand),
+            error("seti 1 2")
+        );
+        assert_eq!(
+            (1, 12, "4".to_string(), ParseErrorKind::UnexpectedToken),
+            error("seti 1 2 3 4")
+        );
+        assert_eq!(
+            (1, 5, "-1".to_string(), ParseErrorKind::BadRegister),
+            error("#ip -1")
+        );
+
+        // Register ranges are only checked against a machine size.
+        assert!("addr 0 7 1".parse::<Program>().is_ok());
+        let e = Program::parse_checked("seti 7 0 1\naddr 0 7 1", 6).unwrap_err();
+        assert_eq!((2, 8), (e.line, e.column));
+        assert_eq!(ParseErrorKind::RegisterOutOfRange(6), e.kind);
+        assert_eq!(
+            "2:1: unknown opcode `addz`",
+            "seti 1 2 3\naddz 1 2 3"
+                .parse::<Program>()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_eval() {
+        // Example from day 16
+        let before = [3, 2, 1, 1];
+        let matching = OpType::ALL
+            .iter()
+            .filter(|op| op.eval(2, 1, &before) == 2)
+            .cloned()
+            .collect::<Vec<OpType>>();
+        assert_eq!(vec![OpType::Addi, OpType::Mulr, OpType::Seti], matching);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut machine = Machine::new(get_test_program(), 6);
+        assert_eq!(5, machine.run());
+        assert_eq!(vec![6, 5, 6, 0, 0, 9], machine.reg);
+    }
+
+    #[test]
+    fn test_run_without_ip_reg() {
+        let program = Program {
+            ip_reg: None,
+            instrs: vec![
+                Instr::new(OpType::Seti, 7, 0, 0),
+                Instr::new(OpType::Muli, 0, 3, 1),
+            ],
+        };
+        let mut machine = Machine::new(program, 4);
+        assert_eq!(2, machine.run());
+        assert_eq!(vec![7, 21, 0, 0], machine.reg);
+    }
+}
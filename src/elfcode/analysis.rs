@@ -0,0 +1,504 @@
+// Static checks over a program: which instructions can run at all, which registers are live
+// at each instruction, and which register operands don't exist in the machine.
+//
+// Where a computed jump can go comes from the range of values each register might hold, found
+// by running the program over ranges instead of values.
+
+use super::cfg::{Cfg, Target};
+use super::{OpType, Program, Word};
+use std::fmt;
+use std::fmt::Formatter;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// The ip is bound to a register the machine doesn't have.
+    IpRegisterOutOfRange(usize),
+    /// The instruction at `pc` names a register the machine doesn't have.
+    RegisterOutOfRange { pc: usize, reg: Word },
+    /// Instructions `start..end` can never run.
+    Unreachable { start: usize, end: usize },
+    /// The instruction at `pc` writes a register that is never read afterwards.
+    DeadStore { pc: usize, reg: usize },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Warning::IpRegisterOutOfRange(reg) => write!(f, "ip register r{} out of range", reg),
+            Warning::RegisterOutOfRange { pc, reg } => {
+                write!(f, "{}: register r{} out of range", pc, reg)
+            }
+            Warning::Unreachable { start, end } if end - start == 1 => {
+                write!(f, "{}: unreachable", start)
+            }
+            Warning::Unreachable { start, end } => {
+                write!(f, "{}-{}: unreachable", start, end - 1)
+            }
+            Warning::DeadStore { pc, reg } => write!(f, "{}: r{} is never read", pc, reg),
+        }
+    }
+}
+
+/// The values a register might hold: every value from `lo` to `hi` inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub lo: Word,
+    pub hi: Word,
+}
+
+impl Range {
+    /// Nothing is known about the register.
+    pub const ANY: Range = Range {
+        lo: Word::MIN,
+        hi: Word::MAX,
+    };
+
+    pub fn exactly(value: Word) -> Range {
+        Range {
+            lo: value,
+            hi: value,
+        }
+    }
+
+    fn join(self, other: Range) -> Range {
+        Range {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// Joined with `other`, giving up on any bound that moved, so that loops settle.
+    fn widen(self, other: Range) -> Range {
+        Range {
+            lo: if other.lo < self.lo {
+                Word::MIN
+            } else {
+                self.lo
+            },
+            hi: if other.hi > self.hi {
+                Word::MAX
+            } else {
+                self.hi
+            },
+        }
+    }
+
+    /// The values `op` might store, given the ranges of its inputs.
+    fn eval(op: OpType, a: Range, b: Range) -> Range {
+        let flag = |always: bool, never: bool| match (always, never) {
+            (true, _) => Range::exactly(1),
+            (_, true) => Range::exactly(0),
+            _ => Range { lo: 0, hi: 1 },
+        };
+        match op {
+            OpType::Addr | OpType::Addi => match (a.lo.checked_add(b.lo), a.hi.checked_add(b.hi)) {
+                (Some(lo), Some(hi)) => Range { lo, hi },
+                _ => Range::ANY,
+            },
+            OpType::Mulr | OpType::Muli => {
+                let corners = [
+                    a.lo.checked_mul(b.lo),
+                    a.lo.checked_mul(b.hi),
+                    a.hi.checked_mul(b.lo),
+                    a.hi.checked_mul(b.hi),
+                ];
+                match (corners.iter().min().unwrap(), corners.iter().max().unwrap()) {
+                    (&Some(lo), &Some(hi)) => Range { lo, hi },
+                    _ => Range::ANY,
+                }
+            }
+            OpType::Banr | OpType::Bani => match (a.lo >= 0, b.lo >= 0) {
+                (true, true) => Range {
+                    lo: 0,
+                    hi: a.hi.min(b.hi),
+                },
+                (true, false) => Range { lo: 0, hi: a.hi },
+                (false, true) => Range { lo: 0, hi: b.hi },
+                (false, false) => Range::ANY,
+            },
+            OpType::Borr | OpType::Bori if a.lo >= 0 && b.lo >= 0 => {
+                // No bit is set above the highest either input can have.
+                let bits = 64 - (a.hi | b.hi).leading_zeros();
+                Range {
+                    lo: a.lo.max(b.lo),
+                    hi: if bits >= 63 {
+                        Word::MAX
+                    } else {
+                        (1 << bits) - 1
+                    },
+                }
+            }
+            OpType::Borr | OpType::Bori => Range::ANY,
+            OpType::Setr | OpType::Seti => a,
+            OpType::Gtir | OpType::Gtri | OpType::Gtrr => flag(a.lo > b.hi, a.hi <= b.lo),
+            OpType::Eqir | OpType::Eqri | OpType::Eqrr => {
+                flag(a.lo == a.hi && a == b, a.hi < b.lo || b.hi < a.lo)
+            }
+        }
+    }
+}
+
+/// The range of values the instruction at `pc` stores, given the registers before it.
+fn result(program: &Program, pc: usize, regs: &[Range]) -> Range {
+    let instr = &program.instrs[pc];
+    let input = |value: Word, is_reg: bool| {
+        if !is_reg {
+            Range::exactly(value)
+        } else if program.ip_reg == Some(value as usize) {
+            Range::exactly(pc as Word)
+        } else if value >= 0 {
+            regs.get(value as usize).cloned().unwrap_or(Range::ANY)
+        } else {
+            Range::ANY
+        }
+    };
+    Range::eval(
+        instr.op,
+        input(instr.a, instr.op.a_is_reg()),
+        input(instr.b, instr.op.b_is_reg()),
+    )
+}
+
+/// Where control can go after the instruction at `pc`, given the registers before it.  A jump
+/// whose target is unknown can go anywhere.
+pub fn successors(program: &Program, pc: usize, regs: &[Range]) -> Vec<Target> {
+    let len = program.len() as Word;
+    if program.ip_reg != Some(program.instrs[pc].c) {
+        return vec![if pc as Word + 1 < len {
+            Target::Instr(pc + 1)
+        } else {
+            Target::Halt
+        }];
+    }
+    let ip = result(program, pc, regs);
+    let (lo, hi) = (ip.lo.saturating_add(1), ip.hi.saturating_add(1));
+    let mut targets = (lo.max(0)..=hi.min(len - 1))
+        .map(|t| Target::Instr(t as usize))
+        .collect::<Vec<Target>>();
+    if lo < 0 || hi >= len {
+        targets.push(Target::Halt);
+    }
+    targets
+}
+
+/// What the registers might hold before each instruction when the program starts at `pc` with
+/// registers in `entry`, or `None` where control can't reach.  Registers a loop changes are
+/// widened, usually to an unbounded range.
+pub fn ranges(program: &Program, pc: usize, entry: &[Range]) -> Vec<Option<Vec<Range>>> {
+    const WIDEN_AFTER: usize = 8;
+    let len = program.len();
+    let mut before: Vec<Option<Vec<Range>>> = vec![None; len];
+    // How many times each register's range before each instruction has grown.
+    let mut changes = vec![vec![0; entry.len()]; len];
+    let mut todo = vec![(pc, entry.to_vec())];
+    while let Some((pc, mut regs)) = todo.pop() {
+        if pc >= len {
+            continue;
+        }
+        if let Some(r) = program.ip_reg.filter(|&r| r < regs.len()) {
+            regs[r] = Range::exactly(pc as Word);
+        }
+        if let Some(old) = &before[pc] {
+            let mut merged = old.clone();
+            for (r, (o, n)) in old.iter().zip(&regs).enumerate() {
+                merged[r] = o.join(*n);
+                if merged[r] != *o {
+                    changes[pc][r] += 1;
+                    if changes[pc][r] > WIDEN_AFTER {
+                        merged[r] = o.widen(*n);
+                    }
+                }
+            }
+            if &merged == old {
+                continue;
+            }
+            regs = merged;
+        }
+        let mut after = regs.clone();
+        let c = program.instrs[pc].c;
+        if c < after.len() {
+            after[c] = result(program, pc, &regs);
+        }
+        for target in successors(program, pc, &regs) {
+            if let Target::Instr(t) = target {
+                todo.push((t, after.clone()));
+            }
+        }
+        before[pc] = Some(regs);
+    }
+    before
+}
+
+pub struct Analysis {
+    pub cfg: Cfg,
+    pub reachable: Vec<bool>,
+    /// The registers' ranges before each reachable instruction, starting from any values.
+    pub ranges: Vec<Option<Vec<Range>>>,
+    /// Bit r is set if register r may be read before it is written, starting at each
+    /// instruction.  The ip register is never live, since it's set before every instruction.
+    pub live_in: Vec<u64>,
+    /// As `live_in`, but just after each instruction.
+    pub live_out: Vec<u64>,
+    pub warnings: Vec<Warning>,
+}
+
+fn bit(reg: Word, num_regs: usize) -> u64 {
+    if reg >= 0 && (reg as usize) < num_regs {
+        1 << reg
+    } else {
+        0
+    }
+}
+
+/// Analyse a program for a machine with `num_regs` registers (at most 64).  Registers in
+/// `live_at_halt` are the program's result, so writes to them are never dead.
+pub fn analyze(program: &Program, num_regs: usize, live_at_halt: &[usize]) -> Analysis {
+    assert!(num_regs <= 64, "Liveness supports at most 64 registers");
+    let len = program.len();
+    let ip_mask = program.ip_reg.map_or(0, |r| bit(r as Word, num_regs));
+    let mut warnings = Vec::new();
+
+    if let Some(r) = program.ip_reg {
+        if r >= num_regs {
+            warnings.push(Warning::IpRegisterOutOfRange(r));
+        }
+    }
+    let mut uses = vec![0u64; len];
+    let mut defs = vec![0u64; len];
+    for (pc, instr) in program.instrs.iter().enumerate() {
+        let operands = [
+            (instr.a, instr.op.a_is_reg()),
+            (instr.b, instr.op.b_is_reg()),
+            (instr.c as Word, true),
+        ];
+        for &(reg, is_reg) in operands.iter() {
+            if is_reg && (reg < 0 || reg as usize >= num_regs) {
+                warnings.push(Warning::RegisterOutOfRange { pc, reg });
+            }
+        }
+        for &(reg, is_reg) in operands[..2].iter() {
+            if is_reg {
+                uses[pc] |= bit(reg, num_regs) & !ip_mask;
+            }
+        }
+        defs[pc] = bit(instr.c as Word, num_regs) & !ip_mask;
+    }
+
+    // Reachability, and where each computed jump can go, from register ranges.
+    let ranges = ranges(program, 0, &vec![Range::ANY; num_regs]);
+    let reachable = ranges.iter().map(Option::is_some).collect::<Vec<bool>>();
+    let mut pc = 0;
+    while pc < len {
+        if reachable[pc] {
+            pc += 1;
+            continue;
+        }
+        let start = pc;
+        while pc < len && !reachable[pc] {
+            pc += 1;
+        }
+        warnings.push(Warning::Unreachable { start, end: pc });
+    }
+
+    // Liveness, iterated backwards to a fixed point.
+    let succs = ranges
+        .iter()
+        .enumerate()
+        .map(|(pc, regs)| regs.as_ref().map_or(vec![], |r| successors(program, pc, r)))
+        .collect::<Vec<Vec<Target>>>();
+    let halt_mask = live_at_halt
+        .iter()
+        .fold(0, |mask, &r| mask | bit(r as Word, num_regs));
+    let mut live_in = vec![0u64; len];
+    let mut live_out = vec![0u64; len];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pc in (0..len).rev() {
+            let out = succs[pc].iter().fold(0, |mask, t| match *t {
+                Target::Instr(s) => mask | live_in[s],
+                Target::Halt => mask | halt_mask,
+            });
+            let live = uses[pc] | (out & !defs[pc]);
+            if out != live_out[pc] || live != live_in[pc] {
+                live_out[pc] = out;
+                live_in[pc] = live;
+                changed = true;
+            }
+        }
+    }
+    for pc in 0..len {
+        if reachable[pc] && defs[pc] != 0 && live_out[pc] & defs[pc] == 0 {
+            warnings.push(Warning::DeadStore {
+                pc,
+                reg: program.instrs[pc].c,
+            });
+        }
+    }
+
+    Analysis {
+        cfg: Cfg::build(program),
+        reachable,
+        ranges,
+        live_in,
+        live_out,
+        warnings,
+    }
+}
+
+impl Analysis {
+    /// The registers in a liveness mask, e.g. "r0 r3".
+    pub fn registers(mask: u64) -> String {
+        (0..64)
+            .filter(|r| mask & (1 << r) != 0)
+            .map(|r| format!("r{}", r))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// The program listing with the registers live into each instruction, followed by the
+    /// warnings.
+    pub fn render(&self, program: &Program) -> String {
+        let mut out = String::new();
+        for (pc, instr) in program.instrs.iter().enumerate() {
+            let live = if self.reachable[pc] {
+                Analysis::registers(self.live_in[pc])
+            } else {
+                "unreachable".to_string()
+            };
+            out.push_str(&format!("{:>3}: {:<20} {}\n", pc, instr.to_string(), live));
+        }
+        for warning in &self.warnings {
+            out.push_str(&format!("warning: {}\n", warning));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reachability() {
+        let program = Program::parse(
+            "#ip 3
+            seti 5 0 1
+            addi 3 2 3
+            seti 1 0 0
+            seti 2 0 0
+            addr 1 1 0
+            seti 9 0 3
+            seti 1 0 2",
+        );
+        let analysis = analyze(&program, 4, &[0]);
+        assert_eq!(
+            vec![true, true, false, false, true, true, false],
+            analysis.reachable
+        );
+        assert_eq!(
+            vec![
+                Warning::Unreachable { start: 2, end: 4 },
+                Warning::Unreachable { start: 6, end: 7 },
+            ],
+            analysis.warnings
+        );
+        assert_eq!("2-3: unreachable", analysis.warnings[0].to_string());
+    }
+
+    #[test]
+    fn test_computed_jump() {
+        // `ip += r1` with r1 = 2 isn't a skip: it jumps over two instructions.
+        let program = Program::parse(
+            "#ip 3
+            seti 2 0 1
+            seti 7 0 2
+            addr 1 3 3
+            seti 0 0 2
+            seti 9 0 3
+            addr 2 2 0",
+        );
+        let analysis = analyze(&program, 4, &[0]);
+        assert_eq!(
+            vec![true, true, true, false, false, true],
+            analysis.reachable
+        );
+        assert_eq!(
+            vec![Warning::Unreachable { start: 3, end: 5 }],
+            analysis.warnings
+        );
+
+        // With r1 = r0 & 3 it could land on any of the next four instructions, or halt.
+        let program = Program::parse("#ip 3\nbani 0 3 1\nseti 7 0 2\naddr 1 3 3\nseti 0 0 2");
+        let regs = vec![Range::ANY; 4];
+        let ranges = ranges(&program, 0, &regs);
+        assert_eq!(Range { lo: 0, hi: 3 }, ranges[2].as_ref().unwrap()[1]);
+        assert_eq!(
+            vec![Target::Instr(3), Target::Halt],
+            successors(&program, 2, ranges[2].as_ref().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        // A counter loop: r0 is widened until it could wrap round, so nothing is known about it,
+        // while r1 keeps its constant and r2 is a flag.
+        let program = Program::parse(
+            "#ip 3
+            seti 10 0 1
+            addi 0 1 0
+            gtrr 0 1 2
+            addr 2 3 3
+            seti 0 0 3",
+        );
+        let ranges = ranges(&program, 0, &[Range::exactly(0); 4]);
+        let at = |pc: usize, r: usize| ranges[pc].as_ref().unwrap()[r];
+        assert_eq!(Range::ANY, at(1, 0));
+        assert_eq!(Range::exactly(10), at(3, 1));
+        assert_eq!(Range { lo: 0, hi: 1 }, at(3, 2));
+    }
+
+    #[test]
+    fn test_liveness() {
+        // r0 counts up to r1 in a loop; r2 is scratch and r3 is written but never used.
+        let program = Program::parse(
+            "#ip 4
+            seti 7 0 3
+            addi 0 1 0
+            gtrr 0 1 2
+            addr 2 4 4
+            seti 0 0 4",
+        );
+        let analysis = analyze(&program, 5, &[0]);
+        assert_eq!(vec![0b011, 0b011, 0b011, 0b111, 0b011], analysis.live_in);
+        assert_eq!("r0 r1", Analysis::registers(analysis.live_out[1]));
+        assert_eq!(
+            vec![Warning::DeadStore { pc: 0, reg: 3 }],
+            analysis.warnings
+        );
+        assert!(analysis
+            .render(&program)
+            .contains("  3: addr 2 4 4           r0 r1 r2\n"));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let program = Program::parse("#ip 6\naddr 0 7 1\nseti 7 0 2\nsetr -1 0 9");
+        let analysis = analyze(&program, 6, &[0]);
+        assert_eq!(
+            vec![
+                Warning::IpRegisterOutOfRange(6),
+                Warning::RegisterOutOfRange { pc: 0, reg: 7 },
+                Warning::RegisterOutOfRange { pc: 2, reg: -1 },
+                Warning::RegisterOutOfRange { pc: 2, reg: 9 },
+                Warning::DeadStore { pc: 0, reg: 1 },
+                Warning::DeadStore { pc: 1, reg: 2 },
+            ],
+            analysis.warnings
+        );
+        assert_eq!(
+            "2: register r-1 out of range",
+            analysis.warnings[2].to_string()
+        );
+    }
+}
@@ -0,0 +1,405 @@
+// An assembler for writing Elfcode by hand.  It accepts everything `Program::parse` does, plus:
+//
+//     ; comment                 everything after a ';' is ignored
+//     #ip ip                    the ip register may be given by name, and is also named `ip`
+//     .reg name 3               name a register; `r3` always names register 3
+//     .const NAME expr          define a constant
+//     label:                    the address of the next instruction
+//     .macro name p1 p2 ...     define a macro; the body runs to `.end`, with each parameter
+//     .end                      replaced by the corresponding argument
+//     jmp expr                  continue at the given address
+//     call expr reg             jump, saving the return address in reg
+//     ret reg                   return to the address saved by `call`
+//     halt                      jump past the end of the program
+//
+// Immediate operands are expressions of numbers, constants and labels joined by `+` and `-`,
+// written without spaces.  `$end` is the number of instructions in the program.  The jump
+// macros need an ip register.
+
+use super::{Instr, OpType, Program, Word};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    /// One-based line number in the source.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+const BUILTINS: [&str; 4] = ["jmp", "call", "ret", "halt"];
+const MAX_MACRO_DEPTH: usize = 16;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Vec<String>>,
+}
+
+#[derive(Default)]
+struct Assembler {
+    ip_reg: Option<usize>,
+    regs: HashMap<String, usize>,
+    symbols: HashMap<String, Word>,
+    macros: HashMap<String, Macro>,
+    /// Instructions with their operands still as text, and the line they came from.
+    pending: Vec<(usize, OpType, Vec<String>)>,
+}
+
+impl Assembler {
+    fn reg(&self, token: &str) -> Result<usize, String> {
+        if let Some(&r) = self.regs.get(token) {
+            return Ok(r);
+        }
+        token
+            .strip_prefix('r')
+            .unwrap_or(token)
+            .parse()
+            .map_err(|_| format!("Unknown register {}", token))
+    }
+
+    fn expr(&self, token: &str) -> Result<Word, String> {
+        let mut total = 0;
+        let mut rest = token;
+        let mut sign = 1;
+        if rest.starts_with('-') {
+            sign = -1;
+            rest = &rest[1..];
+        }
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = &rest[..end];
+            let value = match (term.parse::<Word>(), self.symbols.get(term)) {
+                (Ok(n), _) => n,
+                (_, Some(&v)) => v,
+                _ if term.is_empty() => return Err(format!("Bad expression {}", token)),
+                _ => return Err(format!("Unknown symbol {}", term)),
+            };
+            total += sign * value;
+            if end == rest.len() {
+                return Ok(total);
+            }
+            sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn define(&mut self, name: &str, value: Word) -> Result<(), String> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn ip_name(&self, mnemonic: &str) -> Result<String, String> {
+        match self.ip_reg {
+            Some(r) => Ok(r.to_string()),
+            None => Err(format!("{} needs an #ip register", mnemonic)),
+        }
+    }
+
+    /// Handle one line of source, already split into words, outside any macro definition.
+    fn statement(&mut self, line: usize, words: &[String], depth: usize) -> Result<(), String> {
+        let (head, args) = (words[0].as_str(), &words[1..]);
+        let expect = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("{} takes {} operands", head, n))
+            }
+        };
+        let expand = |asm: &mut Assembler, lines: Vec<Vec<String>>| {
+            lines
+                .iter()
+                .try_for_each(|words| asm.statement(line, words, depth + 1))
+        };
+        if depth > MAX_MACRO_DEPTH {
+            return Err(format!("Macro expansion of {} is too deep", head));
+        }
+        match head {
+            "#ip" => {
+                expect(1)?;
+                let r = self.reg(&args[0])?;
+                self.ip_reg = Some(r);
+                self.regs.insert("ip".to_string(), r);
+            }
+            ".reg" => {
+                expect(2)?;
+                let r = self.reg(&args[1])?;
+                self.regs.insert(args[0].clone(), r);
+            }
+            ".const" => {
+                expect(2)?;
+                let value = self.expr(&args[1])?;
+                self.define(&args[0], value)?;
+            }
+            "jmp" => {
+                expect(1)?;
+                let ip = self.ip_name(head)?;
+                expand(
+                    self,
+                    vec![words_of(&["seti", &format!("{}-1", args[0]), "0", &ip])],
+                )?;
+            }
+            "call" => {
+                expect(2)?;
+                let ip = self.ip_name(head)?;
+                expand(
+                    self,
+                    vec![
+                        words_of(&["addi", &ip, "1", &args[1]]),
+                        words_of(&["seti", &format!("{}-1", args[0]), "0", &ip]),
+                    ],
+                )?;
+            }
+            "ret" => {
+                expect(1)?;
+                let ip = self.ip_name(head)?;
+                expand(self, vec![words_of(&["setr", &args[0], "0", &ip])])?;
+            }
+            "halt" => {
+                expect(0)?;
+                let ip = self.ip_name(head)?;
+                expand(self, vec![words_of(&["seti", "$end", "0", &ip])])?;
+            }
+            _ => {
+                if let Ok(op) = head.parse::<OpType>() {
+                    expect(3)?;
+                    self.pending.push((line, op, args.to_vec()));
+                } else if let Some(mac) = self.macros.get(head) {
+                    if args.len() != mac.params.len() {
+                        return Err(format!("{} takes {} operands", head, mac.params.len()));
+                    }
+                    let lines = mac
+                        .body
+                        .iter()
+                        .map(|words| {
+                            words
+                                .iter()
+                                .map(|w| match mac.params.iter().position(|p| p == w) {
+                                    Some(i) => args[i].clone(),
+                                    None => w.clone(),
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    expand(self, lines)?;
+                } else {
+                    return Err(format!("Unknown instruction {}", head));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, line: usize, op: OpType, args: &[String]) -> Result<Instr, AsmError> {
+        let operand = |token: &str, is_reg: bool| {
+            if is_reg {
+                self.reg(token).map(|r| r as Word)
+            } else {
+                self.expr(token)
+            }
+        };
+        (|| {
+            Ok(Instr::new(
+                op,
+                operand(&args[0], op.a_is_reg())?,
+                operand(&args[1], op.b_is_reg())?,
+                self.reg(&args[2])?,
+            ))
+        })()
+        .map_err(|message| AsmError { line, message })
+    }
+}
+
+fn words_of(words: &[&str]) -> Vec<String> {
+    words.iter().map(|w| w.to_string()).collect()
+}
+
+/// Assemble source into a program; `Program`'s `Display` gives the plain text form.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut asm = Assembler::default();
+    let mut defining: Option<(usize, String, Macro)> = None;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { line, message };
+        let code = text.split(';').next().unwrap();
+        let mut words = code
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_string())
+            .collect::<Vec<String>>();
+
+        if let Some((start, name, mut mac)) = defining.take() {
+            match words.first().map(|w| w.as_str()) {
+                Some(".end") => {
+                    asm.macros.insert(name, mac);
+                }
+                Some(w) if w.ends_with(':') => {
+                    return Err(error("Labels aren't allowed in macros".to_string()));
+                }
+                Some(_) => {
+                    mac.body.push(words);
+                    defining = Some((start, name, mac));
+                }
+                None => defining = Some((start, name, mac)),
+            }
+            continue;
+        }
+
+        while words.first().is_some_and(|w| w.ends_with(':')) {
+            let label = words.remove(0);
+            let address = asm.pending.len() as Word;
+            asm.define(label.trim_end_matches(':'), address)
+                .map_err(error)?;
+        }
+        match words.first().map(|w| w.as_str()) {
+            None => (),
+            Some(".macro") => {
+                let name = words
+                    .get(1)
+                    .ok_or_else(|| error("Missing macro name".to_string()))?;
+                if name.parse::<OpType>().is_ok() || BUILTINS.contains(&name.as_str()) {
+                    return Err(error(format!("Can't redefine {}", name)));
+                }
+                let mac = Macro {
+                    params: words[2..].to_vec(),
+                    body: Vec::new(),
+                };
+                defining = Some((line, name.clone(), mac));
+            }
+            Some(_) => asm.statement(line, &words, 0).map_err(error)?,
+        }
+    }
+    if let Some((line, name, _)) = defining {
+        return Err(AsmError {
+            line,
+            message: format!("Macro {} has no .end", name),
+        });
+    }
+
+    let len = asm.pending.len() as Word;
+    asm.symbols.insert("$end".to_string(), len);
+    let instrs = asm
+        .pending
+        .iter()
+        .map(|(line, op, args)| asm.resolve(*line, *op, args))
+        .collect::<Result<Vec<Instr>, AsmError>>()?;
+    Ok(Program {
+        ip_reg: asm.ip_reg,
+        instrs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Machine;
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        let source = "#ip 0\nseti 5 0 1\naddr 1 2 3\n";
+        assert_eq!(Ok(Program::parse(source)), assemble(source));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let program = assemble(
+            "#ip 5
+            .reg i 1
+            .reg total r2
+            .const LIMIT 10
+                    seti 0 0 total
+            loop:   addi i 1 i          ; count up to LIMIT
+                    addr total i total
+                    gtri i LIMIT-1 3
+                    addr 3 ip ip
+                    jmp loop
+                    halt",
+        )
+        .unwrap();
+        assert_eq!(
+            Program::parse(
+                "#ip 5
+                seti 0 0 2
+                addi 1 1 1
+                addr 2 1 2
+                gtri 1 9 3
+                addr 3 5 5
+                seti 0 0 5
+                seti 7 0 5"
+            ),
+            program
+        );
+        let mut machine = Machine::new(program, 6);
+        machine.run();
+        assert_eq!(55, machine.reg[2]);
+    }
+
+    #[test]
+    fn test_macros() {
+        let program = assemble(
+            "#ip 5
+            .macro double x
+                addr x x x
+            .end
+            .macro quadruple x
+                double x
+                double x
+            .end
+                seti 3 0 0
+                call sub, r4
+                halt
+            sub:
+                quadruple r0
+                ret r4",
+        )
+        .unwrap();
+        assert_eq!(7, program.len());
+        let mut machine = Machine::new(program, 6);
+        machine.run();
+        assert_eq!(12, machine.reg[0]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Err(AsmError {
+                line: 3,
+                message: "Unknown symbol nowhere".to_string()
+            }),
+            assemble("#ip 0\nseti 1 0 1\njmp nowhere")
+        );
+        assert_eq!(
+            "line 1: jmp needs an #ip register",
+            assemble("jmp 0").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "line 2: Unknown register x",
+            assemble("seti 1 0 1\naddr x 1 1").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "line 1: Unknown register rr3",
+            assemble("addr rr3 1 1").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "line 1: Macro m has no .end",
+            assemble(".macro m\naddr 1 1 1").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "line 2: a is already defined",
+            assemble("a: seti 1 0 1\na: seti 1 0 1")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
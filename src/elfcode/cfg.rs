@@ -0,0 +1,339 @@
+// Control-flow graph construction for Elfcode programs.  Any instruction that writes the
+// instruction pointer register is treated as a jump.
+
+use super::{Instr, OpType, Program, Word};
+use std::collections::BTreeSet;
+
+/// Where a jump lands: either an instruction index inside the program, or outside it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Target {
+    Instr(usize),
+    Halt,
+}
+
+impl Target {
+    fn from_ip(ip: Word, len: usize) -> Target {
+        if ip >= 0 && (ip as usize) < len {
+            Target::Instr(ip as usize)
+        } else {
+            Target::Halt
+        }
+    }
+}
+
+/// How a single instruction affects the instruction pointer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// The instruction does not write the ip register.
+    Next,
+    /// The next ip is known statically.
+    Jump(Target),
+    /// `ip += rN` straight after a comparison wrote rN: a skip of the following instruction
+    /// when the comparison held.
+    Skip(usize),
+    /// The next ip depends on register values in a way we don't model.
+    Computed,
+}
+
+/// The value the instruction at `pc` writes, if its only register input is the ip register.
+fn constant(program: &Program, pc: usize) -> Option<Word> {
+    let instr = &program.instrs[pc];
+    let ip_reg = program.ip_reg;
+    let known = |is_reg: bool, r: Word| !is_reg || ip_reg == Some(r as usize);
+    if !known(instr.op.a_is_reg(), instr.a) || !known(instr.op.b_is_reg(), instr.b) {
+        return None;
+    }
+    let mut reg = vec![0; ip_reg.map_or(0, |r| r + 1)];
+    if let Some(r) = ip_reg {
+        reg[r] = pc as Word;
+    }
+    Some(instr.op.eval(instr.a, instr.b, &reg))
+}
+
+/// The register N if the instruction at `pc` is `ip += rN`.
+fn skip_register(program: &Program, pc: usize) -> Option<usize> {
+    let instr = &program.instrs[pc];
+    let ip_reg = program.ip_reg.filter(|&r| r == instr.c)? as Word;
+    match instr.op {
+        OpType::Addr if instr.a == ip_reg => Some(instr.b as usize),
+        OpType::Addr if instr.b == ip_reg => Some(instr.a as usize),
+        _ => None,
+    }
+}
+
+/// The instruction before `pc`, if it always runs just before `pc` does: it doesn't jump, and
+/// no jump with a static target lands on `pc`.  Computed jumps are assumed not to either.
+fn falls_into(program: &Program, pc: usize) -> Option<&Instr> {
+    let prev = &program.instrs[pc.checked_sub(1)?];
+    if program.ip_reg == Some(prev.c) {
+        return None;
+    }
+    let lands = (0..program.len()).any(|q| {
+        if program.ip_reg != Some(program.instrs[q].c) {
+            return false;
+        }
+        let ip = match skip_register(program, q) {
+            Some(_) if q + 2 == pc => return true,
+            // `ip += rN` after rN was set to a constant.
+            Some(flag) if q > 0 && program.instrs[q - 1].c == flag => {
+                constant(program, q - 1).map(|offset| q as Word + offset)
+            }
+            _ => constant(program, q),
+        };
+        ip == Some(pc as Word - 1)
+    });
+    if lands {
+        None
+    } else {
+        Some(prev)
+    }
+}
+
+/// Decode the effect of the instruction at `pc` on control flow.  `ip += rN` is only a skip
+/// when rN holds a comparison's result; when it holds a constant it's a jump.
+pub fn flow(program: &Program, pc: usize) -> Flow {
+    let instr = &program.instrs[pc];
+    if program.ip_reg != Some(instr.c) {
+        return Flow::Next;
+    }
+    if let Some(ip) = constant(program, pc) {
+        return Flow::Jump(Target::from_ip(ip + 1, program.len()));
+    }
+    let flag = match skip_register(program, pc) {
+        Some(flag) => flag,
+        None => return Flow::Computed,
+    };
+    match falls_into(program, pc) {
+        Some(prev) if prev.c == flag && is_comparison(prev.op) => Flow::Skip(flag),
+        Some(prev) if prev.c == flag => match constant(program, pc - 1) {
+            Some(offset) => Flow::Jump(Target::from_ip(pc as Word + offset + 1, program.len())),
+            None => Flow::Computed,
+        },
+        _ => Flow::Computed,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    /// Unconditional transfer, including falling through to the next block.
+    Goto(Target),
+    /// `ip += rN` after a comparison: goes to `taken` when the flag register holds 1,
+    /// `not_taken` when it holds 0.
+    /// `cond` is the index of the comparison that set the flag, when it immediately precedes
+    /// the jump in the same block.
+    Branch {
+        flag: usize,
+        cond: Option<usize>,
+        taken: Target,
+        not_taken: Target,
+    },
+    /// A jump whose destination we can't determine; the instruction is at `pc`.
+    Computed(usize),
+}
+
+/// A maximal run of instructions `start..end` entered only at `start`.  Instructions
+/// `start..body_end` are ordinary; the rest implement the terminator (the jump itself and, for
+/// branches, the comparison feeding it).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub body_end: usize,
+    pub end: usize,
+    pub term: Terminator,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+}
+
+fn is_comparison(op: OpType) -> bool {
+    matches!(
+        op,
+        OpType::Gtir | OpType::Gtri | OpType::Gtrr | OpType::Eqir | OpType::Eqri | OpType::Eqrr
+    )
+}
+
+impl Cfg {
+    pub fn build(program: &Program) -> Cfg {
+        let len = program.len();
+        let flows = (0..len).map(|pc| flow(program, pc)).collect::<Vec<Flow>>();
+
+        let mut leaders: BTreeSet<usize> = BTreeSet::new();
+        leaders.insert(0);
+        for (pc, f) in flows.iter().enumerate() {
+            match *f {
+                Flow::Next => {}
+                Flow::Jump(t) => {
+                    if let Target::Instr(t) = t {
+                        leaders.insert(t);
+                    }
+                    leaders.insert(pc + 1);
+                }
+                Flow::Skip(_) => {
+                    leaders.insert(pc + 1);
+                    leaders.insert(pc + 2);
+                }
+                Flow::Computed => {
+                    leaders.insert(pc + 1);
+                }
+            }
+        }
+        let leaders = leaders
+            .into_iter()
+            .filter(|&l| l < len)
+            .collect::<Vec<usize>>();
+
+        let mut blocks = Vec::new();
+        for (i, &start) in leaders.iter().enumerate() {
+            let limit = leaders.get(i + 1).cloned().unwrap_or(len);
+            // A jump always ends its block, so it can only be the last instruction.
+            let (body_end, term) = match flows[limit - 1] {
+                Flow::Next => (limit, Terminator::Goto(Target::from_ip(limit as Word, len))),
+                Flow::Jump(t) => (limit - 1, Terminator::Goto(t)),
+                Flow::Skip(flag) => {
+                    let pc = limit - 1;
+                    let cond = if pc > start
+                        && program.instrs[pc - 1].c == flag
+                        && is_comparison(program.instrs[pc - 1].op)
+                    {
+                        Some(pc - 1)
+                    } else {
+                        None
+                    };
+                    let term = Terminator::Branch {
+                        flag,
+                        cond,
+                        taken: Target::from_ip(pc as Word + 2, len),
+                        not_taken: Target::from_ip(pc as Word + 1, len),
+                    };
+                    (cond.unwrap_or(pc), term)
+                }
+                Flow::Computed => (limit - 1, Terminator::Computed(limit - 1)),
+            };
+            blocks.push(Block {
+                start,
+                body_end,
+                end: limit,
+                term,
+            });
+        }
+        Cfg { blocks }
+    }
+
+    /// Index of the block containing instruction `pc`.
+    pub fn block_at(&self, pc: usize) -> Option<usize> {
+        match self.blocks.binary_search_by_key(&pc, |b| b.start) {
+            Ok(i) => Some(i),
+            Err(0) => None,
+            Err(i) if pc < self.blocks[i - 1].end => Some(i - 1),
+            Err(_) => None,
+        }
+    }
+
+    /// Instruction-level successor targets of a block.  Computed jumps could go anywhere.
+    pub fn successors(&self, block: usize) -> Vec<Target> {
+        match self.blocks[block].term {
+            Terminator::Goto(t) => vec![t],
+            Terminator::Branch {
+                taken, not_taken, ..
+            } => vec![not_taken, taken],
+            Terminator::Computed(_) => self
+                .blocks
+                .iter()
+                .map(|b| Target::Instr(b.start))
+                .chain(Some(Target::Halt))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow() {
+        let program = Program::parse(
+            "#ip 5
+            addi 5 16 5
+            eqrr 3 1 3
+            addr 3 5 5
+            mulr 5 5 5
+            setr 2 0 5
+            addi 4 1 4",
+        );
+        assert_eq!(Flow::Jump(Target::Halt), flow(&program, 0));
+        assert_eq!(Flow::Next, flow(&program, 1));
+        assert_eq!(Flow::Skip(3), flow(&program, 2));
+        assert_eq!(Flow::Jump(Target::Halt), flow(&program, 3));
+        assert_eq!(Flow::Computed, flow(&program, 4));
+        assert_eq!(Flow::Next, flow(&program, 5));
+
+        // Only a comparison's result makes `ip += rN` a skip.  A constant offset is a jump,
+        // and any other value, or a flag some other jump could arrive with, is computed.
+        let program = Program::parse(
+            "#ip 4
+            seti 2 0 1
+            addr 1 4 4
+            mulr 0 0 1
+            addr 4 1 4
+            eqri 0 5 1
+            addr 1 4 4
+            seti 4 0 4",
+        );
+        assert_eq!(Flow::Jump(Target::Instr(4)), flow(&program, 1));
+        assert_eq!(Flow::Computed, flow(&program, 3));
+        assert_eq!(Flow::Computed, flow(&program, 5));
+    }
+
+    #[test]
+    fn test_build() {
+        let program = Program::parse(
+            "#ip 2
+            seti 10 0 0
+            addi 0 -1 0
+            eqri 0 0 1
+            addr 1 2 2
+            seti 0 0 2
+            seti 99 0 3",
+        );
+        let cfg = Cfg::build(&program);
+        assert_eq!(
+            vec![
+                Block {
+                    start: 0,
+                    body_end: 1,
+                    end: 1,
+                    term: Terminator::Goto(Target::Instr(1)),
+                },
+                Block {
+                    start: 1,
+                    body_end: 2,
+                    end: 4,
+                    term: Terminator::Branch {
+                        flag: 1,
+                        cond: Some(2),
+                        taken: Target::Instr(5),
+                        not_taken: Target::Instr(4),
+                    },
+                },
+                Block {
+                    start: 4,
+                    body_end: 4,
+                    end: 5,
+                    term: Terminator::Goto(Target::Instr(1)),
+                },
+                Block {
+                    start: 5,
+                    body_end: 6,
+                    end: 6,
+                    term: Terminator::Goto(Target::Halt),
+                },
+            ],
+            cfg.blocks
+        );
+        assert_eq!(Some(1), cfg.block_at(3));
+        assert_eq!(Some(3), cfg.block_at(5));
+    }
+}
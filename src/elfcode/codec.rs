@@ -0,0 +1,271 @@
+// Conversions between mnemonic programs, the numeric `opcode a b c` form used by day 16, and a
+// compact binary form.
+//
+// An opcode map lists the operation for each opcode number, as inferred by day 16.  The
+// numeric text form keeps any `#ip N` header.  The binary form is:
+//
+//     b"ELF" 1          magic and version
+//     ip                the ip register (below 255), or 255 for none
+//     count             varint
+//     count times:      op (its index in `OpType::ALL`), then a and b as zigzag varints and
+//                       c as a varint
+
+use super::{Instr, OpType, Program, Word};
+use std::fmt;
+use std::fmt::Formatter;
+
+const MAGIC: &[u8] = b"ELF\x01";
+const NO_IP: u8 = 255;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodecError {
+    /// The opcode map has no number for this operation.
+    Unmapped(OpType),
+    /// The opcode map has no operation for this number.
+    UnknownOpcode(Word),
+    /// An instruction writes to a negative register.
+    BadRegister(Word),
+    /// A line of numeric text isn't four numbers or an `#ip` header.
+    BadLine(usize),
+    /// Binary data is malformed at this offset.
+    BadData(usize),
+    /// The ip register is too large for the binary form.
+    BadIpRegister(usize),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CodecError::Unmapped(op) => write!(f, "No opcode for {}", op),
+            CodecError::UnknownOpcode(code) => write!(f, "Unknown opcode {}", code),
+            CodecError::BadRegister(r) => write!(f, "Bad register {}", r),
+            CodecError::BadLine(line) => write!(f, "Bad instruction on line {}", line),
+            CodecError::BadData(offset) => write!(f, "Bad data at offset {}", offset),
+            CodecError::BadIpRegister(r) => write!(f, "Ip register {} out of range", r),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub fn encode(instr: &Instr, map: &[OpType]) -> Result<[Word; 4], CodecError> {
+    match map.iter().position(|&op| op == instr.op) {
+        Some(code) => Ok([code as Word, instr.a, instr.b, instr.c as Word]),
+        None => Err(CodecError::Unmapped(instr.op)),
+    }
+}
+
+/// Decode one numeric instruction, given as `[opcode, a, b, c]`.
+pub fn decode(words: [Word; 4], map: &[OpType]) -> Result<Instr, CodecError> {
+    let [code, a, b, c] = words;
+    if c < 0 {
+        return Err(CodecError::BadRegister(c));
+    }
+    match map.get(code as usize) {
+        Some(&op) if code >= 0 => Ok(Instr::new(op, a, b, c as usize)),
+        _ => Err(CodecError::UnknownOpcode(code)),
+    }
+}
+
+pub fn to_numeric(program: &Program, map: &[OpType]) -> Result<String, CodecError> {
+    let mut out = String::new();
+    if let Some(ip_reg) = program.ip_reg {
+        out.push_str(&format!("#ip {}\n", ip_reg));
+    }
+    for instr in &program.instrs {
+        let [code, a, b, c] = encode(instr, map)?;
+        out.push_str(&format!("{} {} {} {}\n", code, a, b, c));
+    }
+    Ok(out)
+}
+
+pub fn from_numeric(text: &str, map: &[OpType]) -> Result<Program, CodecError> {
+    let mut program = Program::default();
+    for (index, line) in text.lines().enumerate() {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let bad = CodecError::BadLine(index + 1);
+        match words[..] {
+            [] => (),
+            ["#ip", r] => program.ip_reg = Some(r.parse().map_err(|_| bad.clone())?),
+            [_, _, _, _] => {
+                let mut nums = [0; 4];
+                for (num, word) in nums.iter_mut().zip(words) {
+                    *num = word.parse().map_err(|_| bad.clone())?;
+                }
+                program.instrs.push(decode(nums, map)?);
+            }
+            _ => return Err(bad),
+        }
+    }
+    Ok(program)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value: Word) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> Word {
+    (value >> 1) as Word ^ -((value & 1) as Word)
+}
+
+pub fn to_bytes(program: &Program) -> Result<Vec<u8>, CodecError> {
+    let mut out = MAGIC.to_vec();
+    out.push(match program.ip_reg {
+        Some(r) if r >= NO_IP as usize => return Err(CodecError::BadIpRegister(r)),
+        Some(r) => r as u8,
+        None => NO_IP,
+    });
+    put_varint(&mut out, program.len() as u64);
+    for instr in &program.instrs {
+        out.push(instr.op as u8);
+        put_varint(&mut out, zigzag(instr.a));
+        put_varint(&mut out, zigzag(instr.b));
+        put_varint(&mut out, instr.c as u64);
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, CodecError> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or(CodecError::BadData(self.pos))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64, CodecError> {
+        let start = self.pos;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(CodecError::BadData(start))
+    }
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Program, CodecError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(CodecError::BadData(0));
+    }
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len(),
+    };
+    let ip_reg = match reader.byte()? {
+        NO_IP => None,
+        r => Some(r as usize),
+    };
+    let count = reader.varint()?;
+    let mut instrs = Vec::new();
+    for _ in 0..count {
+        let pos = reader.pos;
+        let op = *OpType::ALL
+            .get(reader.byte()? as usize)
+            .ok_or(CodecError::BadData(pos))?;
+        let a = unzigzag(reader.varint()?);
+        let b = unzigzag(reader.varint()?);
+        let c = reader.varint()? as usize;
+        instrs.push(Instr::new(op, a, b, c));
+    }
+    if reader.pos != bytes.len() {
+        return Err(CodecError::BadData(reader.pos));
+    }
+    Ok(Program { ip_reg, instrs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_program() -> Program {
+        Program::parse(
+            "#ip 0
+            seti 5 0 1
+            seti -6 0 2
+            addi 0 1 0
+            addr 1 2 3
+            muli 1 10551236 0",
+        )
+    }
+
+    #[test]
+    fn test_numeric() {
+        let map = OpType::ALL.iter().rev().cloned().collect::<Vec<OpType>>();
+        let numeric = to_numeric(&get_test_program(), &map).unwrap();
+        assert_eq!(
+            "#ip 0\n6 5 0 1\n6 -6 0 2\n14 0 1 0\n15 1 2 3\n12 1 10551236 0\n",
+            numeric
+        );
+        assert_eq!(Ok(get_test_program()), from_numeric(&numeric, &map));
+    }
+
+    #[test]
+    fn test_numeric_errors() {
+        assert_eq!(
+            Err(CodecError::Unmapped(OpType::Addi)),
+            to_numeric(&get_test_program(), &[OpType::Seti])
+        );
+        assert_eq!(
+            Err(CodecError::UnknownOpcode(3)),
+            from_numeric("0 1 2 3\n3 1 2 3", &OpType::ALL[..3])
+        );
+        assert_eq!(
+            Err(CodecError::BadLine(2)),
+            from_numeric("0 1 2 3\n0 1 2", &OpType::ALL)
+        );
+        assert_eq!(
+            Err(CodecError::BadRegister(-3)),
+            from_numeric("0 1 2 -3", &OpType::ALL)
+        );
+    }
+
+    #[test]
+    fn test_bytes() {
+        let bytes = to_bytes(&get_test_program()).unwrap();
+        assert_eq!(&b"ELF\x01\x00\x05\x09\x0a\x00\x01"[..], &bytes[..10]);
+        assert_eq!(Ok(get_test_program()), from_bytes(&bytes));
+
+        let program = Program::parse("addr 1 2 3");
+        assert_eq!(
+            Ok(program.clone()),
+            from_bytes(&to_bytes(&program).unwrap())
+        );
+        assert_eq!(
+            Err(CodecError::BadData(bytes.len() - 1)),
+            from_bytes(&bytes[..bytes.len() - 1])
+        );
+        assert_eq!(Err(CodecError::BadData(0)), from_bytes(b"ELF"));
+    }
+
+    #[test]
+    fn test_bytes_ip_register() {
+        let mut program = Program::parse("#ip 254\naddr 1 2 3");
+        assert_eq!(
+            Ok(program.clone()),
+            from_bytes(&to_bytes(&program).unwrap())
+        );
+        program.ip_reg = Some(255);
+        assert_eq!(Err(CodecError::BadIpRegister(255)), to_bytes(&program));
+        program.ip_reg = Some(256);
+        assert_eq!(Err(CodecError::BadIpRegister(256)), to_bytes(&program));
+    }
+}
@@ -0,0 +1,300 @@
+// Compiles a program into a vector of closures, one per instruction, with register indices and
+// immediates baked in.  Reads of the ip register become constants, so the ip register only
+// needs to be written by instructions that jump.
+
+use super::width::{Overflow, Trap, TrapKind};
+use super::{Instr, Machine, OpType, Word};
+use std::convert::TryFrom;
+
+type Op = Box<dyn Fn(&mut [Word]) -> usize>;
+
+#[derive(Clone, Copy)]
+enum Src {
+    Reg(usize),
+    Imm(Word),
+}
+
+fn next_ip(value: Word) -> usize {
+    // An ip that doesn't fit is as far outside the program as any other.
+    value
+        .checked_add(1)
+        .and_then(|next| usize::try_from(next).ok())
+        .unwrap_or(usize::MAX)
+}
+
+/// Panics as `Machine::step` does when checked arithmetic overflows.
+fn overflowed(pc: usize, instr: Instr, a: Word, b: Word) -> ! {
+    let kind = TrapKind::Overflow { a, b };
+    panic!("{}", Trap { pc, instr, kind })
+}
+
+// Builds a closure computing `$body` from the two inputs, specialised on whether each input is
+// a register or a constant.  Jumps return the new ip; everything else returns `next`.
+macro_rules! compile_op {
+    ($a:expr, $b:expr, $c:expr, $jump:expr, $next:expr, |$x:ident, $y:ident| $body:expr) => {{
+        let (c, jump, next) = ($c, $jump, $next);
+        match ($a, $b) {
+            (Src::Reg(a), Src::Reg(b)) => Box::new(move |r: &mut [Word]| {
+                let ($x, $y) = (r[a], r[b]);
+                r[c] = $body;
+                if jump {
+                    next_ip(r[c])
+                } else {
+                    next
+                }
+            }) as Op,
+            (Src::Reg(a), Src::Imm(b)) => Box::new(move |r: &mut [Word]| {
+                let ($x, $y) = (r[a], b);
+                r[c] = $body;
+                if jump {
+                    next_ip(r[c])
+                } else {
+                    next
+                }
+            }) as Op,
+            (Src::Imm(a), Src::Reg(b)) => Box::new(move |r: &mut [Word]| {
+                let ($x, $y) = (a, r[b]);
+                r[c] = $body;
+                if jump {
+                    next_ip(r[c])
+                } else {
+                    next
+                }
+            }) as Op,
+            (Src::Imm(a), Src::Imm(b)) => Box::new(move |r: &mut [Word]| {
+                let ($x, $y) = (a, b);
+                r[c] = $body;
+                if jump {
+                    next_ip(r[c])
+                } else {
+                    next
+                }
+            }) as Op,
+        }
+    }};
+}
+
+pub struct Compiled {
+    ops: Vec<Op>,
+    writes_ip: Vec<bool>,
+    ip_reg: Option<usize>,
+}
+
+impl Compiled {
+    /// Compile the machine's program, including any natives installed by `Machine::optimize`,
+    /// with the machine's overflow behaviour.  Panics if the program names a register the
+    /// machine doesn't have.
+    pub fn new(machine: &Machine) -> Compiled {
+        let program = &machine.program;
+        let overflow = machine.overflow;
+        let num_regs = machine.reg.len();
+        let ip_reg = program.ip_reg;
+        let check = |r: usize| {
+            if r >= num_regs {
+                panic!("Register r{} out of range for {} registers", r, num_regs);
+            }
+            r
+        };
+        if let Some(r) = ip_reg {
+            check(r);
+        }
+
+        let mut ops: Vec<Op> = Vec::with_capacity(program.len());
+        let mut writes_ip = Vec::with_capacity(program.len());
+        for (pc, &instr) in program.instrs.iter().enumerate() {
+            if let Some(&native) = machine.natives.get(&pc) {
+                ops.push(Box::new(move |r: &mut [Word]| native.execute(r)));
+                writes_ip.push(true);
+                continue;
+            }
+            let src = |value: Word, is_reg: bool| {
+                if !is_reg {
+                    Src::Imm(value)
+                } else if Some(value as usize) == ip_reg {
+                    Src::Imm(pc as Word)
+                } else {
+                    Src::Reg(check(value as usize))
+                }
+            };
+            let a = src(instr.a, instr.op.a_is_reg());
+            let b = src(instr.b, instr.op.b_is_reg());
+            let c = check(instr.c);
+            let jump = Some(c) == ip_reg;
+            let next = pc + 1;
+            ops.push(match instr.op {
+                OpType::Addr | OpType::Addi => match overflow {
+                    Overflow::Wrapping => {
+                        compile_op!(a, b, c, jump, next, |x, y| x.wrapping_add(y))
+                    }
+                    Overflow::Checked => compile_op!(a, b, c, jump, next, |x, y| x
+                        .checked_add(y)
+                        .unwrap_or_else(|| overflowed(pc, instr, x, y))),
+                    Overflow::Saturating => {
+                        compile_op!(a, b, c, jump, next, |x, y| x.saturating_add(y))
+                    }
+                },
+                OpType::Mulr | OpType::Muli => match overflow {
+                    Overflow::Wrapping => {
+                        compile_op!(a, b, c, jump, next, |x, y| x.wrapping_mul(y))
+                    }
+                    Overflow::Checked => compile_op!(a, b, c, jump, next, |x, y| x
+                        .checked_mul(y)
+                        .unwrap_or_else(|| overflowed(pc, instr, x, y))),
+                    Overflow::Saturating => {
+                        compile_op!(a, b, c, jump, next, |x, y| x.saturating_mul(y))
+                    }
+                },
+                OpType::Banr | OpType::Bani => compile_op!(a, b, c, jump, next, |x, y| x & y),
+                OpType::Borr | OpType::Bori => compile_op!(a, b, c, jump, next, |x, y| x | y),
+                OpType::Setr | OpType::Seti => compile_op!(a, b, c, jump, next, |x, _y| x),
+                OpType::Gtir | OpType::Gtri | OpType::Gtrr => {
+                    compile_op!(a, b, c, jump, next, |x, y| (x > y) as Word)
+                }
+                OpType::Eqir | OpType::Eqri | OpType::Eqrr => {
+                    compile_op!(a, b, c, jump, next, |x, y| (x == y) as Word)
+                }
+            });
+            writes_ip.push(jump);
+        }
+        Compiled {
+            ops,
+            writes_ip,
+            ip_reg,
+        }
+    }
+
+    /// Run from the machine's current state until it halts or, after at least one instruction,
+    /// reaches `stop`.  Leaves the machine exactly as interpreting would, and returns the number
+    /// of instructions executed.
+    fn execute(&self, machine: &mut Machine, stop: Option<usize>) -> usize {
+        let reg = &mut machine.reg[..];
+        let mut ip = machine.ip;
+        let mut last = None;
+        let mut steps = 0;
+        while let Some(op) = self.ops.get(ip) {
+            if steps > 0 && Some(ip) == stop {
+                break;
+            }
+            last = Some(ip);
+            ip = op(reg);
+            steps += 1;
+        }
+        if let (Some(ip_reg), Some(last)) = (self.ip_reg, last) {
+            if !self.writes_ip[last] {
+                reg[ip_reg] = last as Word;
+            }
+        }
+        machine.ip = ip;
+        steps
+    }
+
+    /// Run until the program halts, returning the number of instructions executed.
+    pub fn run(&self, machine: &mut Machine) -> usize {
+        self.execute(machine, None)
+    }
+
+    /// Run until the instruction pointer reaches `pc`, after executing at least one
+    /// instruction.  Returns the number of instructions executed, or None if the program halted.
+    pub fn run_to(&self, machine: &mut Machine, pc: usize) -> Option<usize> {
+        let steps = self.execute(machine, Some(pc));
+        if machine.is_halted() {
+            None
+        } else {
+            Some(steps)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Instr, Program};
+    use super::*;
+
+    #[test]
+    fn test_matches_interpreter() {
+        let program = Program::parse(
+            "#ip 4
+            seti 7 0 0
+            muli 0 3 1
+            gtir 20 1 2
+            addr 2 4 4
+            addi 4 2 4
+            eqri 1 21 3
+            bori 3 8 3
+            banr 3 0 2
+            setr 4 0 5
+            mulr 4 4 4",
+        );
+        let mut slow = Machine::new(program.clone(), 6);
+        let mut fast = Machine::new(program, 6);
+        let steps = slow.run();
+        assert_eq!(steps, Compiled::new(&fast).run(&mut fast));
+        assert_eq!(slow.reg, fast.reg);
+        assert_eq!(slow.ip, fast.ip);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let program = Program::parse("seti 9223372036854775807 0 0\naddi 0 1 0\nmuli 0 2 1");
+        for &(overflow, expected) in &[
+            (Overflow::Wrapping, [Word::MIN, 0]),
+            (Overflow::Saturating, [Word::MAX, Word::MAX]),
+        ] {
+            let mut slow = Machine::with_overflow(program.clone(), 2, overflow);
+            let mut fast = slow.clone();
+            slow.run();
+            Compiled::new(&fast).run(&mut fast);
+            assert_eq!(expected[..], slow.reg[..]);
+            assert_eq!(expected[..], fast.reg[..]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "1: addi 0 1 0: addi of 9223372036854775807 and 1 overflows")]
+    fn test_checked_overflow() {
+        let program = Program::parse("seti 9223372036854775807 0 0\naddi 0 1 0");
+        let mut machine = Machine::with_overflow(program, 1, Overflow::Checked);
+        Compiled::new(&machine).run(&mut machine);
+    }
+
+    #[test]
+    fn test_run_to() {
+        let program = Program::parse(
+            "#ip 2
+            seti 0 0 0
+            addi 0 1 0
+            gtri 0 2 1
+            addr 1 2 2
+            seti 0 0 2",
+        );
+        let mut machine = Machine::new(program, 3);
+        let compiled = Compiled::new(&machine);
+        assert_eq!(Some(2), compiled.run_to(&mut machine, 2));
+        assert_eq!(vec![1, 0, 1], machine.reg);
+        assert_eq!(Some(4), compiled.run_to(&mut machine, 2));
+        assert_eq!(2, machine.reg[0]);
+        assert_eq!(None, compiled.run_to(&mut machine, 0));
+        assert_eq!(vec![3, 1, 4], machine.reg);
+    }
+
+    #[test]
+    fn test_without_ip_reg() {
+        let program = Program {
+            ip_reg: None,
+            instrs: vec![
+                Instr::new(OpType::Seti, 3, 0, 0),
+                Instr::new(OpType::Mulr, 0, 0, 1),
+            ],
+        };
+        let mut machine = Machine::new(program, 4);
+        assert_eq!(2, Compiled::new(&machine).run(&mut machine));
+        assert_eq!(vec![3, 9, 0, 0], machine.reg);
+    }
+
+    #[test]
+    #[should_panic(expected = "Register r7 out of range")]
+    fn test_bad_register() {
+        let machine = Machine::new(Program::parse("seti 1 0 7"), 4);
+        Compiled::new(&machine);
+    }
+}
@@ -0,0 +1,456 @@
+// Breakpoints, watchpoints and single-stepping on top of `Machine`, plus the command language
+// used by the `elfdbg` REPL.
+
+use super::{Machine, Word};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Formatter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg(usize),
+    Value(Word),
+}
+
+impl Operand {
+    fn parse(s: &str) -> Result<Operand, String> {
+        if let Some(reg) = s.strip_prefix('r') {
+            return reg
+                .parse()
+                .map(Operand::Reg)
+                .map_err(|_| format!("Bad register {}", s));
+        }
+        s.parse()
+            .map(Operand::Value)
+            .map_err(|_| format!("Bad value {}", s))
+    }
+
+    fn value(self, reg: &[Word]) -> Word {
+        match self {
+            Operand::Reg(r) => reg.get(r).cloned().unwrap_or(0),
+            Operand::Value(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(r) => write!(f, "r{}", r),
+            Operand::Value(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// A comparison between registers and/or constants, e.g. `r3 > 100`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub op: CmpOp,
+    pub rhs: Operand,
+}
+
+impl Condition {
+    pub fn parse(s: &str) -> Result<Condition, String> {
+        let words = s.split_whitespace().collect::<Vec<&str>>();
+        if words.len() != 3 {
+            return Err(format!("Expected `<operand> <op> <operand>`, got `{}`", s));
+        }
+        let op = match words[1] {
+            "==" => CmpOp::Eq,
+            "!=" => CmpOp::Ne,
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            ">=" => CmpOp::Ge,
+            other => return Err(format!("Unknown comparison {}", other)),
+        };
+        Ok(Condition {
+            lhs: Operand::parse(words[0])?,
+            op,
+            rhs: Operand::parse(words[2])?,
+        })
+    }
+
+    pub fn holds(&self, reg: &[Word]) -> bool {
+        let (l, r) = (self.lhs.value(reg), self.rhs.value(reg));
+        match self.op {
+            CmpOp::Eq => l == r,
+            CmpOp::Ne => l != r,
+            CmpOp::Lt => l < r,
+            CmpOp::Le => l <= r,
+            CmpOp::Gt => l > r,
+            CmpOp::Ge => l >= r,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let op = match self.op {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        };
+        write!(f, "{} {} {}", self.lhs, op, self.rhs)
+    }
+}
+
+/// Why execution stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested number of steps completed.
+    Stepped,
+    /// About to execute the instruction at a breakpoint.
+    Breakpoint(usize),
+    /// A watched register changed value.
+    Watchpoint { reg: usize, old: Word, new: Word },
+    /// The instruction pointer left the program.
+    Halted,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Stop::Stepped => write!(f, "Stepped"),
+            Stop::Breakpoint(pc) => write!(f, "Breakpoint at {}", pc),
+            Stop::Watchpoint { reg, old, new } => write!(f, "r{} changed {} -> {}", reg, old, new),
+            Stop::Halted => write!(f, "Halted"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    pub machine: Machine,
+    pub steps: usize,
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    watchpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Debugger {
+        Debugger {
+            machine,
+            steps: 0,
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Break before executing instruction `pc`, optionally only when `cond` holds.
+    pub fn add_breakpoint(&mut self, pc: usize, cond: Option<Condition>) {
+        self.breakpoints.insert(pc, cond);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (&usize, &Option<Condition>)> {
+        self.breakpoints.iter()
+    }
+
+    /// Stop whenever register `reg` changes value.
+    pub fn add_watchpoint(&mut self, reg: usize) {
+        self.watchpoints.insert(reg);
+    }
+
+    pub fn remove_watchpoint(&mut self, reg: usize) -> bool {
+        self.watchpoints.remove(&reg)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &usize> {
+        self.watchpoints.iter()
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        match self.breakpoints.get(&self.machine.ip) {
+            Some(Some(cond)) => cond.holds(&self.machine.reg),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Execute one instruction, reporting a watchpoint if it changed a watched register.
+    fn step_one(&mut self) -> Option<Stop> {
+        let before = self.machine.reg.clone();
+        if !self.machine.step() {
+            return Some(Stop::Halted);
+        }
+        self.steps += 1;
+        self.watchpoints
+            .iter()
+            .find(|&&r| before.get(r) != self.machine.reg.get(r))
+            .map(|&reg| Stop::Watchpoint {
+                reg,
+                old: before[reg],
+                new: self.machine.reg[reg],
+            })
+    }
+
+    /// Execute up to `count` instructions, stopping early at watchpoints or breakpoints.
+    pub fn step(&mut self, count: usize) -> Stop {
+        for i in 0..count {
+            if i > 0 && self.at_breakpoint() {
+                return Stop::Breakpoint(self.machine.ip);
+            }
+            if let Some(stop) = self.step_one() {
+                return stop;
+            }
+        }
+        if self.machine.is_halted() {
+            Stop::Halted
+        } else {
+            Stop::Stepped
+        }
+    }
+
+    /// Run until a breakpoint, watchpoint or halt.  The instruction at the current ip always
+    /// executes, so continuing from a breakpoint makes progress.
+    pub fn cont(&mut self) -> Stop {
+        let mut first = true;
+        loop {
+            if !first && self.at_breakpoint() {
+                return Stop::Breakpoint(self.machine.ip);
+            }
+            first = false;
+            if let Some(stop) = self.step_one() {
+                return stop;
+            }
+        }
+    }
+}
+
+/// A line of input to the debugger REPL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Break(usize, Option<Condition>),
+    Delete(usize),
+    Watch(usize),
+    Unwatch(usize),
+    Registers,
+    SetReg(usize, Word),
+    SetIp(usize),
+    List,
+    /// Show the execution profile, or start sampling a register at an instruction.
+    Profile(Option<(usize, usize)>),
+    /// Write a trace of the next n instructions (or until halted) to a file.
+    Trace(String, Option<u64>),
+    /// Check execution against a saved trace.
+    Replay(String),
+    Help,
+    Quit,
+}
+
+fn parse_num<T: std::str::FromStr>(s: Option<&str>, what: &str) -> Result<T, String> {
+    let s = s.ok_or_else(|| format!("Missing {}", what))?;
+    s.parse().map_err(|_| format!("Bad {} {}", what, s))
+}
+
+fn parse_reg(s: Option<&str>) -> Result<usize, String> {
+    let s = s.ok_or_else(|| "Missing register".to_string())?;
+    parse_num(Some(s.trim_start_matches('r')), "register")
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Ok(Command::Step(1)),
+        };
+        let command = match cmd {
+            "s" | "step" => match words.next() {
+                Some(n) => Command::Step(parse_num(Some(n), "count")?),
+                None => Command::Step(1),
+            },
+            "c" | "continue" => Command::Continue,
+            "b" | "break" => {
+                let pc = parse_num(words.next(), "instruction")?;
+                let cond = match words.next() {
+                    Some("if") => {
+                        let rest = words.by_ref().collect::<Vec<&str>>();
+                        Some(Condition::parse(&rest.join(" "))?)
+                    }
+                    Some(other) => return Err(format!("Expected `if`, got {}", other)),
+                    None => None,
+                };
+                Command::Break(pc, cond)
+            }
+            "d" | "delete" => Command::Delete(parse_num(words.next(), "instruction")?),
+            "w" | "watch" => Command::Watch(parse_reg(words.next())?),
+            "uw" | "unwatch" => Command::Unwatch(parse_reg(words.next())?),
+            "r" | "regs" => Command::Registers,
+            "set" => match words.next() {
+                Some("ip") => Command::SetIp(parse_num(words.next(), "instruction")?),
+                reg => {
+                    let reg = parse_reg(reg)?;
+                    Command::SetReg(reg, parse_num(words.next(), "value")?)
+                }
+            },
+            "l" | "list" => Command::List,
+            "p" | "profile" => match words.next() {
+                Some(pc) => {
+                    let pc = parse_num(Some(pc), "instruction")?;
+                    Command::Profile(Some((pc, parse_reg(words.next())?)))
+                }
+                None => Command::Profile(None),
+            },
+            "t" | "trace" => {
+                let file = words.next().ok_or_else(|| "Missing file".to_string())?;
+                let count = match words.next() {
+                    Some(n) => Some(parse_num(Some(n), "count")?),
+                    None => None,
+                };
+                Command::Trace(file.to_string(), count)
+            }
+            "replay" => {
+                let file = words.next().ok_or_else(|| "Missing file".to_string())?;
+                Command::Replay(file.to_string())
+            }
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            other => return Err(format!("Unknown command {}", other)),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("Unexpected {}", extra)),
+            None => Ok(command),
+        }
+    }
+}
+
+pub const HELP: &str = "Commands:
+  s, step [n]            execute n instructions (default 1; an empty line steps once)
+  c, continue            run until a breakpoint, watchpoint or halt
+  b, break <pc> [if <a> <op> <b>]
+                         break before instruction pc, e.g. `b 28 if r3 == r0`
+  d, delete <pc>         remove the breakpoint at pc
+  w, watch <reg>         stop when a register changes
+  uw, unwatch <reg>      remove a watchpoint
+  r, regs                show ip and registers
+  set rN <value>         modify a register
+  set ip <pc>            move the instruction pointer
+  l, list                show the instructions around ip
+  p, profile [<pc> <reg>]
+                         show hit counts, or sample a register's values at pc
+  t, trace <file> [n]    run n instructions (default: until halted), writing a JSON Lines trace
+  replay <file>          run against a saved trace, stopping at the first difference
+  h, help                show this message
+  q, quit                exit";
+
+#[cfg(test)]
+mod tests {
+    use super::super::Program;
+    use super::*;
+
+    fn get_test_debugger() -> Debugger {
+        let program = Program::parse(
+            "#ip 2
+            seti 0 0 0
+            addi 0 1 0
+            gtri 0 9 1
+            addr 1 2 2
+            seti 0 0 2
+            seti 7 0 3",
+        );
+        Debugger::new(Machine::new(program, 4))
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Ok(Command::Step(1)), Command::parse(""));
+        assert_eq!(Ok(Command::Step(10)), Command::parse("s 10"));
+        assert_eq!(
+            Ok(Command::Break(
+                3,
+                Some(Condition {
+                    lhs: Operand::Reg(0),
+                    op: CmpOp::Ge,
+                    rhs: Operand::Value(5),
+                })
+            )),
+            Command::parse("b 3 if r0 >= 5")
+        );
+        assert_eq!(Ok(Command::SetReg(2, -4)), Command::parse("set r2 -4"));
+        assert_eq!(Ok(Command::SetIp(0)), Command::parse("set ip 0"));
+        assert_eq!(
+            Ok(Command::Profile(Some((28, 3)))),
+            Command::parse("p 28 r3")
+        );
+        assert_eq!(
+            Ok(Command::Trace("out.jsonl".to_string(), Some(100))),
+            Command::parse("t out.jsonl 100")
+        );
+        assert_eq!(
+            Ok(Command::Replay("out.jsonl".to_string())),
+            Command::parse("replay out.jsonl")
+        );
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("b 3 when r0 > 1").is_err());
+        assert!(Command::parse("s 1 2").is_err());
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut dbg = get_test_debugger();
+        dbg.add_breakpoint(2, None);
+        assert_eq!(Stop::Breakpoint(2), dbg.cont());
+        assert_eq!(vec![1, 0, 1, 0], dbg.machine.reg);
+        assert_eq!(Stop::Breakpoint(2), dbg.cont());
+        assert_eq!(2, dbg.machine.reg[0]);
+        assert!(dbg.remove_breakpoint(2));
+        assert_eq!(Stop::Halted, dbg.cont());
+        assert_eq!(vec![10, 1, 5, 7], dbg.machine.reg);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut dbg = get_test_debugger();
+        dbg.add_breakpoint(3, Some(Condition::parse("r1 == 1").unwrap()));
+        assert_eq!(Stop::Breakpoint(3), dbg.cont());
+        assert_eq!(10, dbg.machine.reg[0]);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut dbg = get_test_debugger();
+        dbg.add_watchpoint(1);
+        dbg.machine.reg[0] = 8;
+        dbg.machine.ip = 1;
+        assert_eq!(
+            Stop::Watchpoint {
+                reg: 1,
+                old: 0,
+                new: 1
+            },
+            dbg.cont()
+        );
+        assert_eq!(3, dbg.machine.ip);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut dbg = get_test_debugger();
+        dbg.add_breakpoint(4, None);
+        assert_eq!(Stop::Stepped, dbg.step(3));
+        assert_eq!(3, dbg.machine.ip);
+        assert_eq!(Stop::Breakpoint(4), dbg.step(5));
+        assert_eq!(4, dbg.steps);
+    }
+}
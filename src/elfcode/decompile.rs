@@ -0,0 +1,803 @@
+// Turns an Elfcode program back into something resembling structured source code.  Writes to
+// the ip register become gotos, `ip += flag` after a comparison becomes an if, natural loops
+// become `loop { ... }`, and anything that won't structure cleanly is left as a labelled goto.
+
+use super::cfg::{flow, Block, Cfg, Flow, Target, Terminator};
+use super::{Instr, OpType, Program};
+use petgraph::algo::dominators::simple_fast;
+use petgraph::graph::{DiGraph, NodeIndex};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fmt::Formatter;
+
+fn operand(value: i64, is_reg: bool, pc: usize, ip_reg: Option<usize>) -> String {
+    if !is_reg {
+        value.to_string()
+    } else if Some(value as usize) == ip_reg {
+        // The ip register always holds the address of the executing instruction.
+        pc.to_string()
+    } else {
+        format!("r{}", value)
+    }
+}
+
+fn operands(instr: &Instr, pc: usize, ip_reg: Option<usize>) -> (String, String) {
+    (
+        operand(instr.a, instr.op.a_is_reg(), pc, ip_reg),
+        operand(instr.b, instr.op.b_is_reg(), pc, ip_reg),
+    )
+}
+
+fn symbol(op: OpType) -> &'static str {
+    match op {
+        OpType::Addr | OpType::Addi => "+",
+        OpType::Mulr | OpType::Muli => "*",
+        OpType::Banr | OpType::Bani => "&",
+        OpType::Borr | OpType::Bori => "|",
+        OpType::Gtir | OpType::Gtri | OpType::Gtrr => ">",
+        OpType::Eqir | OpType::Eqri | OpType::Eqrr => "==",
+        OpType::Setr | OpType::Seti => "",
+    }
+}
+
+/// The value an instruction computes, as an expression.
+fn expression(instr: &Instr, pc: usize, ip_reg: Option<usize>) -> String {
+    let (a, b) = operands(instr, pc, ip_reg);
+    match instr.op {
+        OpType::Setr | OpType::Seti => a,
+        op => format!("{} {} {}", a, symbol(op), b),
+    }
+}
+
+/// Render an instruction that doesn't write the ip register as an assignment.
+fn assignment(instr: &Instr, pc: usize, ip_reg: Option<usize>) -> String {
+    let dst = format!("r{}", instr.c);
+    let (a, b) = operands(instr, pc, ip_reg);
+    match instr.op {
+        OpType::Setr | OpType::Seti => format!("{} = {}", dst, a),
+        OpType::Gtir | OpType::Gtri | OpType::Gtrr | OpType::Eqir | OpType::Eqri | OpType::Eqrr => {
+            format!("{} = ({} {} {})", dst, a, symbol(instr.op), b)
+        }
+        op if a == dst => format!("{} {}= {}", dst, symbol(op), b),
+        op if b == dst && instr.op.b_is_reg() => format!("{} {}= {}", dst, symbol(op), a),
+        op => format!("{} = {} {} {}", dst, a, symbol(op), b),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Gt,
+    Le,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cond {
+    lhs: String,
+    op: CmpOp,
+    rhs: String,
+}
+
+impl Cond {
+    fn negate(self) -> Cond {
+        let op = match self.op {
+            CmpOp::Eq => CmpOp::Ne,
+            CmpOp::Ne => CmpOp::Eq,
+            CmpOp::Gt => CmpOp::Le,
+            CmpOp::Le => CmpOp::Gt,
+        };
+        Cond { op, ..self }
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let op = match self.op {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Gt => ">",
+            CmpOp::Le => "<=",
+        };
+        write!(f, "{} {} {}", self.lhs, op, self.rhs)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stmt {
+    Label(usize),
+    Assign(String),
+    If {
+        cond: Cond,
+        then: Vec<Stmt>,
+        otherwise: Vec<Stmt>,
+    },
+    Loop(Vec<Stmt>),
+    Break,
+    Continue,
+    Halt,
+    Goto(usize),
+    /// A jump to a computed address.
+    Jump(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Dest {
+    Block(usize),
+    Halt,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Context {
+    /// Where control goes after the last statement of the region.
+    follow: Dest,
+    loop_header: Option<Dest>,
+    loop_exit: Option<Dest>,
+}
+
+struct Decompiler<'a> {
+    program: &'a Program,
+    blocks: Vec<Block>,
+    terms: Vec<Term>,
+    /// Loop header block index -> last block index of the loop body.
+    loops: HashMap<usize, usize>,
+}
+
+/// A block terminator with its targets resolved to (surviving) block indices.
+#[derive(Clone, Debug)]
+enum Term {
+    Goto(Dest),
+    Branch {
+        cond: Cond,
+        taken: Dest,
+        not_taken: Dest,
+    },
+    Jump(String),
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(program: &'a Program) -> Decompiler<'a> {
+        let cfg = Cfg::build(program);
+        let is_trampoline =
+            |b: &Block| b.start == b.body_end && matches!(b.term, Terminator::Goto(_));
+
+        // Skip over blocks that do nothing but jump somewhere else.
+        let resolve = |t: Target| -> Target {
+            let mut t = t;
+            let mut seen = HashSet::new();
+            while let Target::Instr(pc) = t {
+                let b = &cfg.blocks[cfg.block_at(pc).unwrap()];
+                if !seen.insert(pc) || !is_trampoline(b) {
+                    break;
+                }
+                if let Terminator::Goto(next) = b.term {
+                    t = next;
+                }
+            }
+            t
+        };
+        let resolved = cfg
+            .blocks
+            .iter()
+            .map(|b| match b.term {
+                Terminator::Goto(t) => Terminator::Goto(resolve(t)),
+                Terminator::Branch {
+                    flag,
+                    cond,
+                    taken,
+                    not_taken,
+                } => Terminator::Branch {
+                    flag,
+                    cond,
+                    taken: resolve(taken),
+                    not_taken: resolve(not_taken),
+                },
+                t => t,
+            })
+            .collect::<Vec<Terminator>>();
+
+        // Drop trampolines that are no longer reachable from anywhere.
+        let mut reachable = vec![false; cfg.blocks.len()];
+        let mut stack = vec![0];
+        while let Some(b) = stack.pop() {
+            if !cfg.blocks.is_empty() && !reachable[b] {
+                reachable[b] = true;
+                let succ = match resolved[b] {
+                    Terminator::Computed(_) => (0..cfg.blocks.len()).collect(),
+                    Terminator::Goto(t) => targets_to_blocks(&cfg, &[t]),
+                    Terminator::Branch {
+                        taken, not_taken, ..
+                    } => targets_to_blocks(&cfg, &[taken, not_taken]),
+                };
+                stack.extend(succ);
+            }
+        }
+        let referenced = resolved
+            .iter()
+            .flat_map(|t| match *t {
+                Terminator::Goto(t) => vec![t],
+                Terminator::Branch {
+                    taken, not_taken, ..
+                } => vec![taken, not_taken],
+                Terminator::Computed(_) => vec![],
+            })
+            .collect::<HashSet<Target>>();
+        let keep = (0..cfg.blocks.len())
+            .filter(|&i| {
+                reachable[i]
+                    || !is_trampoline(&cfg.blocks[i])
+                    || referenced.contains(&Target::Instr(cfg.blocks[i].start))
+            })
+            .collect::<Vec<usize>>();
+
+        let index_of = keep
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (cfg.blocks[old].start, new))
+            .collect::<HashMap<usize, usize>>();
+        let dest = |t: Target| match t {
+            Target::Instr(pc) => Dest::Block(index_of[&pc]),
+            Target::Halt => Dest::Halt,
+        };
+        let blocks = keep.iter().map(|&i| cfg.blocks[i]).collect::<Vec<Block>>();
+        let terms = keep
+            .iter()
+            .map(|&i| match resolved[i] {
+                Terminator::Goto(t) => Term::Goto(dest(t)),
+                Terminator::Branch {
+                    flag,
+                    cond,
+                    taken,
+                    not_taken,
+                } => Term::Branch {
+                    cond: branch_condition(program, flag, cond),
+                    taken: dest(taken),
+                    not_taken: dest(not_taken),
+                },
+                Terminator::Computed(pc) => Term::Jump(format!(
+                    "{} + 1",
+                    expression(&program.instrs[pc], pc, program.ip_reg)
+                )),
+            })
+            .collect::<Vec<Term>>();
+
+        let mut decompiler = Decompiler {
+            program,
+            blocks,
+            terms,
+            loops: HashMap::new(),
+        };
+        decompiler.find_loops();
+        decompiler
+    }
+
+    fn successors(&self, b: usize) -> Vec<usize> {
+        let dests = match self.terms[b] {
+            Term::Goto(d) => vec![d],
+            Term::Branch {
+                taken, not_taken, ..
+            } => vec![not_taken, taken],
+            Term::Jump(_) => (0..self.blocks.len()).map(Dest::Block).collect(),
+        };
+        dests
+            .into_iter()
+            .filter_map(|d| match d {
+                Dest::Block(b) => Some(b),
+                Dest::Halt => None,
+            })
+            .collect()
+    }
+
+    /// Find natural loops whose bodies occupy a contiguous range of blocks.
+    fn find_loops(&mut self) {
+        if self.blocks.is_empty() {
+            return;
+        }
+        let mut graph: DiGraph<(), ()> = DiGraph::new();
+        let nodes = (0..self.blocks.len())
+            .map(|_| graph.add_node(()))
+            .collect::<Vec<NodeIndex>>();
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new(); self.blocks.len()];
+        for b in 0..self.blocks.len() {
+            for s in self.successors(b) {
+                graph.add_edge(nodes[b], nodes[s], ());
+                preds[s].push(b);
+            }
+        }
+        let doms = simple_fast(&graph, nodes[0]);
+        let reachable = |b: usize| doms.dominators(nodes[b]).is_some();
+
+        let mut bodies: HashMap<usize, BTreeSet<usize>> = HashMap::new();
+        for b in 0..self.blocks.len() {
+            let dominators = match doms.dominators(nodes[b]) {
+                Some(d) => d.collect::<HashSet<NodeIndex>>(),
+                None => continue,
+            };
+            for h in self.successors(b) {
+                if !dominators.contains(&nodes[h]) {
+                    continue;
+                }
+                // b -> h is a back edge: the loop is everything that reaches b without passing h.
+                let body = bodies.entry(h).or_default();
+                body.insert(h);
+                let mut stack = vec![b];
+                while let Some(n) = stack.pop() {
+                    if body.insert(n) {
+                        stack.extend(preds[n].iter().cloned());
+                    }
+                }
+            }
+        }
+
+        for (h, body) in bodies {
+            let first = *body.iter().next().unwrap();
+            let last = *body.iter().next_back().unwrap();
+            if first == h && (h..=last).all(|b| body.contains(&b) || !reachable(b)) {
+                self.loops.insert(h, last);
+            }
+        }
+    }
+
+    fn dest_of(&self, b: usize) -> Dest {
+        if b < self.blocks.len() {
+            Dest::Block(b)
+        } else {
+            Dest::Halt
+        }
+    }
+
+    fn jump(&self, d: Dest, ctx: &Context) -> Stmt {
+        if d == Dest::Halt {
+            Stmt::Halt
+        } else if Some(d) == ctx.loop_header {
+            Stmt::Continue
+        } else if Some(d) == ctx.loop_exit {
+            Stmt::Break
+        } else {
+            match d {
+                Dest::Block(b) => Stmt::Goto(self.blocks[b].start),
+                Dest::Halt => Stmt::Halt,
+            }
+        }
+    }
+
+    /// The statement needed to get from the end of a block to `d`, or None if control gets
+    /// there anyway.  `next` is the block emitted immediately afterwards in the same region.
+    fn transfer(&self, d: Dest, next: Option<Dest>, ctx: &Context) -> Option<Stmt> {
+        if Some(d) == next || (next.is_none() && d == ctx.follow) {
+            None
+        } else {
+            Some(self.jump(d, ctx))
+        }
+    }
+
+    /// Structure blocks `lo..hi`.  If `lo` heads a loop that is currently being structured,
+    /// `in_loop` stops us from nesting it within itself.
+    fn structure(&self, lo: usize, hi: usize, ctx: &Context, in_loop: bool) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        let mut i = lo;
+        while i < hi {
+            out.push(Stmt::Label(self.blocks[i].start));
+
+            if let Some(&last) = self.loops.get(&i) {
+                if last < hi && !(in_loop && i == lo) {
+                    let body_ctx = Context {
+                        follow: Dest::Block(i),
+                        loop_header: Some(Dest::Block(i)),
+                        loop_exit: Some(self.dest_of(last + 1)),
+                    };
+                    out.push(Stmt::Loop(self.structure(i, last + 1, &body_ctx, true)));
+                    i = last + 1;
+                    self.fall_off(i, hi, ctx, &mut out);
+                    continue;
+                }
+            }
+
+            let block = &self.blocks[i];
+            for pc in block.start..block.body_end {
+                out.push(Stmt::Assign(assignment(
+                    &self.program.instrs[pc],
+                    pc,
+                    self.program.ip_reg,
+                )));
+            }
+
+            let next = if i + 1 < hi {
+                Some(Dest::Block(i + 1))
+            } else {
+                None
+            };
+            match self.terms[i].clone() {
+                Term::Goto(d) => out.extend(self.transfer(d, next, ctx)),
+                Term::Jump(target) => out.push(Stmt::Jump(target)),
+                Term::Branch {
+                    cond,
+                    taken,
+                    not_taken,
+                } => {
+                    let (cond, taken, not_taken) =
+                        if Some(taken) == next || (next.is_none() && taken == ctx.follow) {
+                            (cond.negate(), not_taken, taken)
+                        } else {
+                            (cond, taken, not_taken)
+                        };
+                    match taken {
+                        Dest::Block(t)
+                            if Some(not_taken) == next
+                                && Some(taken) != ctx.loop_exit
+                                && t > i + 1
+                                && t <= hi =>
+                        {
+                            i = self.structure_if(i, t, hi, cond, ctx, &mut out);
+                            self.fall_off(i, hi, ctx, &mut out);
+                            continue;
+                        }
+                        _ => {
+                            out.push(Stmt::If {
+                                cond,
+                                then: vec![self.jump(taken, ctx)],
+                                otherwise: vec![],
+                            });
+                            out.extend(self.transfer(not_taken, next, ctx));
+                        }
+                    }
+                }
+            }
+            i += 1;
+        }
+        out
+    }
+
+    /// Emit `if !cond { i+1..t } [else { t..u }]` and return the index of the block after it.
+    fn structure_if(
+        &self,
+        i: usize,
+        t: usize,
+        hi: usize,
+        cond: Cond,
+        ctx: &Context,
+        out: &mut Vec<Stmt>,
+    ) -> usize {
+        let join = match self.terms[t - 1] {
+            Term::Goto(Dest::Block(u)) if t - 1 > i && u > t && u <= hi => u,
+            _ => t,
+        };
+        let inner = Context {
+            follow: self.dest_of(join),
+            ..*ctx
+        };
+        let then = self.structure(i + 1, t, &inner, false);
+        let otherwise = self.structure(t, join, &inner, false);
+        out.push(Stmt::If {
+            cond: cond.negate(),
+            then,
+            otherwise,
+        });
+        join
+    }
+
+    /// After a compound statement that ends at block `i`, make sure control reaches the right
+    /// place if that's the end of the region.
+    fn fall_off(&self, i: usize, hi: usize, ctx: &Context, out: &mut Vec<Stmt>) {
+        if i == hi {
+            out.extend(self.transfer(self.dest_of(i), None, ctx));
+        }
+    }
+}
+
+fn targets_to_blocks(cfg: &Cfg, targets: &[Target]) -> Vec<usize> {
+    targets
+        .iter()
+        .filter_map(|t| match *t {
+            Target::Instr(pc) => cfg.block_at(pc),
+            Target::Halt => None,
+        })
+        .collect()
+}
+
+fn branch_condition(program: &Program, flag: usize, cond: Option<usize>) -> Cond {
+    match cond {
+        Some(pc) => {
+            let instr = &program.instrs[pc];
+            let (lhs, rhs) = operands(instr, pc, program.ip_reg);
+            let op = match instr.op {
+                OpType::Gtir | OpType::Gtri | OpType::Gtrr => CmpOp::Gt,
+                _ => CmpOp::Eq,
+            };
+            Cond { lhs, op, rhs }
+        }
+        None => Cond {
+            lhs: format!("r{}", flag),
+            op: CmpOp::Ne,
+            rhs: "0".to_string(),
+        },
+    }
+}
+
+fn collect_gotos(stmts: &[Stmt], gotos: &mut HashSet<usize>) {
+    for s in stmts {
+        match s {
+            Stmt::Goto(pc) => {
+                gotos.insert(*pc);
+            }
+            Stmt::If {
+                then, otherwise, ..
+            } => {
+                collect_gotos(then, gotos);
+                collect_gotos(otherwise, gotos);
+            }
+            Stmt::Loop(body) => collect_gotos(body, gotos),
+            _ => {}
+        }
+    }
+}
+
+fn render(stmts: &[Stmt], depth: usize, labels: &HashSet<usize>, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for s in stmts {
+        match s {
+            Stmt::Label(pc) => {
+                if labels.contains(pc) {
+                    out.push_str(&format!("{}L{}:\n", indent, pc));
+                }
+            }
+            Stmt::Assign(text) => out.push_str(&format!("{}{}\n", indent, text)),
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                out.push_str(&format!("{}if {} {{\n", indent, cond));
+                render(then, depth + 1, labels, out);
+                if !otherwise.is_empty() {
+                    out.push_str(&format!("{}}} else {{\n", indent));
+                    render(otherwise, depth + 1, labels, out);
+                }
+                out.push_str(&format!("{}}}\n", indent));
+            }
+            Stmt::Loop(body) => {
+                out.push_str(&format!("{}loop {{\n", indent));
+                render(body, depth + 1, labels, out);
+                out.push_str(&format!("{}}}\n", indent));
+            }
+            Stmt::Break => out.push_str(&format!("{}break\n", indent)),
+            Stmt::Continue => out.push_str(&format!("{}continue\n", indent)),
+            Stmt::Halt => out.push_str(&format!("{}halt\n", indent)),
+            Stmt::Goto(pc) => out.push_str(&format!("{}goto L{}\n", indent, pc)),
+            Stmt::Jump(target) => out.push_str(&format!("{}jump {}\n", indent, target)),
+        }
+    }
+}
+
+/// Recover structured statements from a program.  The flag register written by a comparison
+/// that feeds a branch is folded into the `if` and not assigned separately.
+pub fn structure(program: &Program) -> Vec<Stmt> {
+    let decompiler = Decompiler::new(program);
+    let ctx = Context {
+        follow: Dest::Halt,
+        loop_header: None,
+        loop_exit: None,
+    };
+    decompiler.structure(0, decompiler.blocks.len(), &ctx, false)
+}
+
+/// Decompile a program to pseudo-code.
+pub fn decompile(program: &Program) -> String {
+    let stmts = structure(program);
+    let mut labels = HashSet::new();
+    collect_gotos(&stmts, &mut labels);
+    let mut out = String::new();
+    render(&stmts, 0, &labels, &mut out);
+    out
+}
+
+/// A listing of the program with each instruction's effect spelled out.
+pub fn disassemble(program: &Program) -> String {
+    let mut out = String::new();
+    if let Some(ip_reg) = program.ip_reg {
+        out.push_str(&format!("#ip {}\n", ip_reg));
+    }
+    let width = program.len().saturating_sub(1).to_string().len();
+    for (pc, instr) in program.instrs.iter().enumerate() {
+        let effect = match flow(program, pc) {
+            Flow::Next => assignment(instr, pc, program.ip_reg),
+            Flow::Jump(Target::Instr(t)) => format!("goto {}", t),
+            Flow::Jump(Target::Halt) => "halt".to_string(),
+            Flow::Skip(flag) => format!("if r{} != 0 goto {}", flag, pc + 2),
+            Flow::Computed => format!("jump {} + 1", expression(instr, pc, program.ip_reg)),
+        };
+        out.push_str(&format!(
+            "{:>width$}: {:<20} {}\n",
+            pc,
+            instr.to_string(),
+            effect,
+            width = width
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let program = Program::parse(
+            "#ip 1
+            seti 5 0 3
+            addr 3 1 1
+            mulr 2 3 2
+            eqrr 2 3 0
+            addr 0 1 1
+            addr 2 1 1
+            muli 1 1 1",
+        );
+        assert_eq!(
+            "#ip 1
+0: seti 5 0 3           r3 = 5
+1: addr 3 1 1           halt
+2: mulr 2 3 2           r2 *= r3
+3: eqrr 2 3 0           r0 = (r2 == r3)
+4: addr 0 1 1           if r0 != 0 goto 6
+5: addr 2 1 1           jump r2 + 5 + 1
+6: muli 1 1 1           halt
+",
+            disassemble(&program)
+        );
+    }
+
+    #[test]
+    fn test_decompile_if_else() {
+        let program = Program::parse(
+            "#ip 4
+            gtri 0 10 1
+            addr 1 4 4
+            addi 4 2 4
+            seti 1 0 2
+            seti 5 0 4
+            seti 2 0 2
+            addi 2 3 3",
+        );
+        assert_eq!(
+            "if r0 > 10 {
+    r2 = 1
+} else {
+    r2 = 2
+}
+r3 = r2 + 3
+",
+            decompile(&program)
+        );
+    }
+
+    #[test]
+    fn test_decompile_day21() {
+        let program = Program::parse(
+            "#ip 5
+            seti 123 0 3
+            bani 3 456 3
+            eqri 3 72 3
+            addr 3 5 5
+            seti 0 0 5
+            seti 0 5 3
+            bori 3 65536 2
+            seti 832312 1 3
+            bani 2 255 1
+            addr 3 1 3
+            bani 3 16777215 3
+            muli 3 65899 3
+            bani 3 16777215 3
+            gtir 256 2 1
+            addr 1 5 5
+            addi 5 1 5
+            seti 27 7 5
+            seti 0 2 1
+            addi 1 1 4
+            muli 4 256 4
+            gtrr 4 2 4
+            addr 4 5 5
+            addi 5 1 5
+            seti 25 1 5
+            addi 1 1 1
+            seti 17 0 5
+            setr 1 7 2
+            seti 7 2 5
+            eqrr 3 0 1
+            addr 1 5 5
+            seti 5 5 5",
+        );
+        assert_eq!(
+            "r3 = 123
+loop {
+    r3 &= 456
+    if r3 == 72 {
+        break
+    }
+}
+r3 = 0
+loop {
+    r2 = r3 | 65536
+    r3 = 832312
+    loop {
+        r1 = r2 & 255
+        r3 += r1
+        r3 &= 16777215
+        r3 *= 65899
+        r3 &= 16777215
+        if 256 > r2 {
+            break
+        }
+        r1 = 0
+        loop {
+            r4 = r1 + 1
+            r4 *= 256
+            if r4 > r2 {
+                break
+            }
+            r1 += 1
+        }
+        r2 = r1
+    }
+    if r3 == r0 {
+        halt
+    }
+}
+",
+            decompile(&program)
+        );
+    }
+
+    #[test]
+    fn test_decompile_day19() {
+        let program = Program::parse(
+            "#ip 5
+            addi 5 16 5
+            seti 1 2 2
+            seti 1 0 4
+            mulr 2 4 3
+            eqrr 3 1 3
+            addr 3 5 5
+            addi 5 1 5
+            addr 2 0 0
+            addi 4 1 4
+            gtrr 4 1 3
+            addr 5 3 5
+            seti 2 4 5
+            addi 2 1 2
+            gtrr 2 1 3
+            addr 3 5 5
+            seti 1 1 5
+            mulr 5 5 5
+            addi 1 2 1
+            seti 0 6 5",
+        );
+        assert_eq!(
+            "goto L17
+L1:
+r2 = 1
+loop {
+    r4 = 1
+    loop {
+        r3 = r2 * r4
+        if r3 == r1 {
+            r0 += r2
+        }
+        r4 += 1
+        if r4 > r1 {
+            break
+        }
+    }
+    r2 += 1
+    if r2 > r1 {
+        halt
+    }
+}
+L17:
+r1 += 2
+goto L1
+",
+            decompile(&program)
+        );
+    }
+}
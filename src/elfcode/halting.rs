@@ -0,0 +1,147 @@
+// Finds the values of r0 that make a program halt, for programs whose only exit is an
+// `eqrr` test of r0 against a value the program generates (as in day 21).  The program is run
+// with the test forced to fail, collecting the generated values until the machine state at the
+// test repeats.
+
+use super::compile::Compiled;
+use super::{Machine, OpType, Program, Word};
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HaltingValues {
+    /// The value that halts the program in the fewest steps.
+    pub first: Word,
+    /// The last new value generated before the sequence cycles; it halts the program in the
+    /// most steps.
+    pub last: Word,
+    /// How many distinct values were generated.
+    pub distinct: usize,
+    /// False if the program halted some other way before the sequence cycled.
+    pub cycled: bool,
+}
+
+/// Find the `eqrr` instruction comparing `target` against another register, returning its
+/// index and the other register.
+pub fn find_exit_test(program: &Program, target: usize) -> Option<(usize, usize)> {
+    program
+        .instrs
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| i.op == OpType::Eqrr)
+        .find_map(|(pc, i)| {
+            if i.a == target as Word && i.b != target as Word {
+                Some((pc, i.b as usize))
+            } else if i.b == target as Word && i.a != target as Word {
+                Some((pc, i.a as usize))
+            } else {
+                None
+            }
+        })
+}
+
+/// Run the machine, sampling register `reg` each time it reaches the `eqrr` at `pc`.  The
+/// comparison is always treated as false so the program keeps running.
+pub fn halting_values(machine: &Machine, pc: usize, reg: usize) -> Option<HaltingValues> {
+    let test = machine.program.instrs[pc];
+    assert_eq!(OpType::Eqrr, test.op, "Instruction {} is not an eqrr", pc);
+
+    let mut machine = machine.clone();
+    machine.optimize();
+    let compiled = Compiled::new(&machine);
+    let mut states: HashSet<Vec<Word>> = HashSet::new();
+    let mut values: HashSet<Word> = HashSet::new();
+    let mut result: Option<HaltingValues> = None;
+
+    // The instruction pointer may already be at the test, so check before running.
+    while machine.ip == pc || compiled.run_to(&mut machine, pc).is_some() {
+        let value = machine.reg[reg];
+        // Everything except the register being compared against determines what happens next.
+        let mut state = machine.reg.clone();
+        state[test.a as usize] = 0;
+        state[test.b as usize] = 0;
+        state[reg] = value;
+        if let Some(ip_reg) = machine.program.ip_reg {
+            state[ip_reg] = 0;
+        }
+        if !states.insert(state) {
+            return result.map(|r| HaltingValues { cycled: true, ..r });
+        }
+        if values.insert(value) {
+            result = Some(match result {
+                None => HaltingValues {
+                    first: value,
+                    last: value,
+                    distinct: 1,
+                    cycled: false,
+                },
+                Some(r) => HaltingValues {
+                    last: value,
+                    distinct: r.distinct + 1,
+                    ..r
+                },
+            });
+        }
+
+        // Step over the test as though it failed.
+        if let Some(ip_reg) = machine.program.ip_reg {
+            machine.reg[ip_reg] = pc as Word;
+        }
+        machine.reg[test.c] = 0;
+        machine.ip = pc + 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generates 5, 2, 7, 4, 1, 6, 3, 0, 5, ... (x = (x + 5) mod 8) and halts when r0 matches.
+    fn get_test_program() -> Program {
+        Program::parse(
+            "#ip 4
+            seti 0 0 3
+            addi 1 5 1
+            bani 1 7 1
+            eqrr 1 0 2
+            addr 2 4 4
+            seti 0 0 4",
+        )
+    }
+
+    #[test]
+    fn test_find_exit_test() {
+        assert_eq!(Some((3, 1)), find_exit_test(&get_test_program(), 0));
+        assert_eq!(None, find_exit_test(&get_test_program(), 3));
+    }
+
+    #[test]
+    fn test_halting_values() {
+        let machine = Machine::new(get_test_program(), 5);
+        assert_eq!(
+            Some(HaltingValues {
+                first: 5,
+                last: 0,
+                distinct: 8,
+                cycled: true,
+            }),
+            halting_values(&machine, 3, 1)
+        );
+    }
+
+    #[test]
+    fn test_halts_another_way() {
+        let mut program = get_test_program();
+        program.instrs[5].a = 6;
+        let machine = Machine::new(program, 5);
+        assert_eq!(
+            Some(HaltingValues {
+                first: 5,
+                last: 5,
+                distinct: 1,
+                cycled: false,
+            }),
+            halting_values(&machine, 3, 1)
+        );
+    }
+}
@@ -0,0 +1,416 @@
+// Recognises common Elfcode loop idioms and replaces them with native code.  Each idiom is
+// described by a template; a match is only used if nothing outside the matched range can jump
+// into the middle of it, given the registers the program starts with.
+
+use super::analysis::{ranges, successors, Range};
+use super::cfg::Target;
+use super::{OpType, Program, Word};
+use std::collections::HashMap;
+
+/// A native replacement for a run of instructions starting at the key in `Machine::natives`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Native {
+    /// Adds the sum of the divisors of `n` to `acc` by trying every product `i * j`.
+    SumOfDivisors {
+        i: usize,
+        j: usize,
+        t: usize,
+        n: usize,
+        acc: usize,
+        ip_reg: usize,
+        exit: usize,
+    },
+    /// Sets `q` to `n / k` by counting up until `(q + 1) * k > n`.
+    Divide {
+        q: usize,
+        t: usize,
+        n: usize,
+        k: Word,
+        ip_reg: usize,
+        exit: usize,
+    },
+}
+
+impl Native {
+    /// The instruction the idiom leaves at, just past its last instruction.
+    pub fn exit(&self) -> usize {
+        match *self {
+            Native::SumOfDivisors { exit, .. } | Native::Divide { exit, .. } => exit,
+        }
+    }
+
+    /// Apply the idiom to the registers, leaving them exactly as the loop would with wrapping
+    /// arithmetic, and return the next instruction pointer.
+    pub fn execute(&self, reg: &mut [Word]) -> usize {
+        match *self {
+            Native::SumOfDivisors {
+                i,
+                j,
+                t,
+                n,
+                acc,
+                ip_reg,
+                exit,
+            } => {
+                let n_val = reg[n];
+                reg[acc] = reg[acc].wrapping_add(sum_of_divisors(n_val));
+                reg[i] = n_val.max(1).wrapping_add(1);
+                reg[j] = n_val.max(1).wrapping_add(1);
+                reg[t] = 1;
+                reg[ip_reg] = exit as Word - 1;
+                exit
+            }
+            Native::Divide {
+                q,
+                t,
+                n,
+                k,
+                ip_reg,
+                exit,
+            } => {
+                reg[q] = reg[n].max(0) / k;
+                reg[t] = 1;
+                reg[ip_reg] = exit as Word - 1;
+                exit
+            }
+        }
+    }
+}
+
+fn sum_of_divisors(n: Word) -> Word {
+    let mut sum: Word = 0;
+    let mut d = 1;
+    while d <= n / d {
+        if n % d == 0 {
+            sum = sum.wrapping_add(d);
+            if d != n / d {
+                sum = sum.wrapping_add(n / d);
+            }
+        }
+        d += 1;
+    }
+    sum
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Pat {
+    /// A register bound to the named variable.  Different names must be different registers.
+    Reg(char),
+    /// The register bound to the instruction pointer.
+    Ip,
+    /// An exact immediate value.
+    Imm(Word),
+    /// An immediate bound to the named variable.
+    Val(char),
+    /// An immediate equal to the template's start address plus the given offset.
+    At(Word),
+    /// An ignored input.
+    Any,
+}
+
+type Template = [(OpType, Pat, Pat, Pat)];
+
+const SUM_OF_DIVISORS: &Template = &[
+    (OpType::Seti, Pat::Imm(1), Pat::Any, Pat::Reg('i')),
+    (OpType::Seti, Pat::Imm(1), Pat::Any, Pat::Reg('j')),
+    (OpType::Mulr, Pat::Reg('i'), Pat::Reg('j'), Pat::Reg('t')),
+    (OpType::Eqrr, Pat::Reg('t'), Pat::Reg('n'), Pat::Reg('t')),
+    (OpType::Addr, Pat::Reg('t'), Pat::Ip, Pat::Ip),
+    (OpType::Addi, Pat::Ip, Pat::Imm(1), Pat::Ip),
+    (OpType::Addr, Pat::Reg('i'), Pat::Reg('a'), Pat::Reg('a')),
+    (OpType::Addi, Pat::Reg('j'), Pat::Imm(1), Pat::Reg('j')),
+    (OpType::Gtrr, Pat::Reg('j'), Pat::Reg('n'), Pat::Reg('t')),
+    (OpType::Addr, Pat::Reg('t'), Pat::Ip, Pat::Ip),
+    (OpType::Seti, Pat::At(1), Pat::Any, Pat::Ip),
+    (OpType::Addi, Pat::Reg('i'), Pat::Imm(1), Pat::Reg('i')),
+    (OpType::Gtrr, Pat::Reg('i'), Pat::Reg('n'), Pat::Reg('t')),
+    (OpType::Addr, Pat::Reg('t'), Pat::Ip, Pat::Ip),
+    (OpType::Seti, Pat::At(0), Pat::Any, Pat::Ip),
+];
+
+const DIVIDE: &Template = &[
+    (OpType::Seti, Pat::Imm(0), Pat::Any, Pat::Reg('q')),
+    (OpType::Addi, Pat::Reg('q'), Pat::Imm(1), Pat::Reg('t')),
+    (OpType::Muli, Pat::Reg('t'), Pat::Val('K'), Pat::Reg('t')),
+    (OpType::Gtrr, Pat::Reg('t'), Pat::Reg('n'), Pat::Reg('t')),
+    (OpType::Addr, Pat::Reg('t'), Pat::Ip, Pat::Ip),
+    (OpType::Addi, Pat::Ip, Pat::Imm(1), Pat::Ip),
+    (OpType::Seti, Pat::Val('X'), Pat::Any, Pat::Ip),
+    (OpType::Addi, Pat::Reg('q'), Pat::Imm(1), Pat::Reg('q')),
+    (OpType::Seti, Pat::At(0), Pat::Any, Pat::Ip),
+];
+
+struct Matcher {
+    start: usize,
+    ip_reg: usize,
+    vars: HashMap<char, Word>,
+}
+
+impl Matcher {
+    fn bind(&mut self, pat: Pat, value: Word, is_reg: bool) -> bool {
+        match pat {
+            Pat::Any => true,
+            Pat::Ip => is_reg && value == self.ip_reg as Word,
+            Pat::Imm(v) => !is_reg && value == v,
+            Pat::At(offset) => !is_reg && value == self.start as Word + offset,
+            Pat::Val(name) => !is_reg && *self.vars.entry(name).or_insert(value) == value,
+            Pat::Reg(name) => {
+                if !is_reg || value == self.ip_reg as Word {
+                    return false;
+                }
+                match self.vars.get(&name) {
+                    Some(&bound) => bound == value,
+                    None => {
+                        let taken = self
+                            .vars
+                            .iter()
+                            .any(|(&other, &v)| is_reg_var(other) && v == value);
+                        if taken {
+                            return false;
+                        }
+                        self.vars.insert(name, value);
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    fn reg(&self, name: char) -> usize {
+        self.vars[&name] as usize
+    }
+
+    fn val(&self, name: char) -> Word {
+        self.vars[&name]
+    }
+}
+
+// Register and immediate variables share one namespace; register variables are lower case.
+fn is_reg_var(name: char) -> bool {
+    name.is_lowercase()
+}
+
+fn commutative(op: OpType) -> bool {
+    matches!(
+        op,
+        OpType::Addr | OpType::Mulr | OpType::Banr | OpType::Borr | OpType::Eqrr
+    )
+}
+
+fn match_template(program: &Program, start: usize, template: &Template) -> Option<Matcher> {
+    let ip_reg = program.ip_reg?;
+    if start + template.len() > program.len() {
+        return None;
+    }
+    let mut m = Matcher {
+        start,
+        ip_reg,
+        vars: HashMap::new(),
+    };
+    for (offset, &(op, pa, pb, pc)) in template.iter().enumerate() {
+        let instr = &program.instrs[start + offset];
+        if instr.op != op || !m.bind(pc, instr.c as Word, true) {
+            return None;
+        }
+        let saved = m.vars.clone();
+        if m.bind(pa, instr.a, op.a_is_reg()) && m.bind(pb, instr.b, op.b_is_reg()) {
+            continue;
+        }
+        m.vars = saved;
+        if !(commutative(op)
+            && m.bind(pa, instr.b, op.b_is_reg())
+            && m.bind(pb, instr.a, op.a_is_reg()))
+        {
+            return None;
+        }
+    }
+    Some(m)
+}
+
+/// Whether any instruction outside `start..end` can jump into `start + 1..end`, given the
+/// registers before each instruction.
+fn entered_from_outside(
+    program: &Program,
+    start: usize,
+    end: usize,
+    ranges: &[Option<Vec<Range>>],
+) -> bool {
+    let inside = |t: &Target| matches!(*t, Target::Instr(t) if t > start && t < end);
+    (0..program.len())
+        .filter(|&pc| pc < start || pc >= end)
+        .any(|pc| match &ranges[pc] {
+            Some(regs) => successors(program, pc, regs).iter().any(inside),
+            None => false,
+        })
+}
+
+fn recognise(program: &Program, start: usize, ranges: &[Option<Vec<Range>>]) -> Option<Native> {
+    if let Some(m) = match_template(program, start, SUM_OF_DIVISORS) {
+        let end = start + SUM_OF_DIVISORS.len();
+        if !entered_from_outside(program, start, end, ranges) {
+            return Some(Native::SumOfDivisors {
+                i: m.reg('i'),
+                j: m.reg('j'),
+                t: m.reg('t'),
+                n: m.reg('n'),
+                acc: m.reg('a'),
+                ip_reg: m.ip_reg,
+                exit: end,
+            });
+        }
+    }
+    if let Some(m) = match_template(program, start, DIVIDE) {
+        let end = start + DIVIDE.len();
+        let exit = m.val('X') + 1;
+        let exit_inside = exit > start as Word && exit < end as Word;
+        if m.val('K') > 0
+            && exit >= 0
+            && !exit_inside
+            && !entered_from_outside(program, start, end, ranges)
+        {
+            return Some(Native::Divide {
+                q: m.reg('q'),
+                t: m.reg('t'),
+                n: m.reg('n'),
+                k: m.val('K'),
+                ip_reg: m.ip_reg,
+                exit: exit as usize,
+            });
+        }
+    }
+    None
+}
+
+/// Find every recognised idiom in the program, keyed by the instruction it starts at, when it
+/// starts at `pc` with registers in the ranges `entry`.
+pub fn optimize(program: &Program, pc: usize, entry: &[Range]) -> HashMap<usize, Native> {
+    let ranges = ranges(program, pc, entry);
+    (0..program.len())
+        .filter_map(|pc| recognise(program, pc, &ranges).map(|n| (pc, n)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::width::Overflow;
+    use super::super::{Instr, Machine};
+    use super::*;
+
+    fn get_divisor_program(n: Word) -> Program {
+        Program::parse(&format!(
+            "#ip 5
+            seti {} 0 1
+            seti 1 2 2
+            seti 1 0 4
+            mulr 2 4 3
+            eqrr 3 1 3
+            addr 3 5 5
+            addi 5 1 5
+            addr 2 0 0
+            addi 4 1 4
+            gtrr 4 1 3
+            addr 5 3 5
+            seti 2 4 5
+            addi 2 1 2
+            gtrr 2 1 3
+            addr 3 5 5
+            seti 1 1 5",
+            n
+        ))
+    }
+
+    #[test]
+    fn test_sum_of_divisors() {
+        assert_eq!(1, sum_of_divisors(1));
+        assert_eq!(28, sum_of_divisors(12));
+        assert_eq!(31, sum_of_divisors(16));
+        assert_eq!(0, sum_of_divisors(0));
+    }
+
+    #[test]
+    fn test_recognise_divisor_sum() {
+        let program = get_divisor_program(12);
+        let natives = optimize(&program, 0, &[Range::exactly(0); 6]);
+        assert_eq!(1, natives.len());
+        assert_eq!(
+            Native::SumOfDivisors {
+                i: 2,
+                j: 4,
+                t: 3,
+                n: 1,
+                acc: 0,
+                ip_reg: 5,
+                exit: 16,
+            },
+            natives[&1]
+        );
+    }
+
+    #[test]
+    fn test_native_matches_interpreter() {
+        for &n in &[-3, 0, 1, 12, 17, 36] {
+            let mut slow = Machine::new(get_divisor_program(n), 6);
+            slow.run();
+            let mut fast = Machine::new(get_divisor_program(n), 6);
+            fast.optimize();
+            assert_eq!(2, fast.run());
+            assert_eq!(slow.reg, fast.reg, "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn test_native_overflow() {
+        // The accumulator wraps, as it would running the loop.
+        let mut slow = Machine::new(get_divisor_program(12), 6);
+        slow.reg[0] = Word::MAX - 1;
+        let mut fast = slow.clone();
+        slow.run();
+        fast.optimize();
+        assert_eq!(2, fast.run());
+        assert_eq!(slow.reg, fast.reg);
+        assert_eq!(Word::MIN + 26, fast.reg[0]);
+
+        let mut checked = Machine::with_overflow(get_divisor_program(12), 6, Overflow::Checked);
+        checked.optimize();
+        assert!(checked.natives.is_empty());
+    }
+
+    #[test]
+    fn test_divide() {
+        let source = |n: Word| {
+            format!(
+                "#ip 5
+                seti {} 0 2
+                seti 0 2 1
+                addi 1 1 4
+                muli 4 256 4
+                gtrr 4 2 4
+                addr 4 5 5
+                addi 5 1 5
+                seti 10 1 5
+                addi 1 1 1
+                seti 1 0 5
+                seti 99 0 3",
+                n
+            )
+        };
+        for &n in &[0, 255, 256, 65535, 70000] {
+            let mut slow = Machine::new(Program::parse(&source(n)), 6);
+            slow.run();
+            let mut fast = Machine::new(Program::parse(&source(n)), 6);
+            fast.optimize();
+            assert_eq!(1, fast.natives.len());
+            fast.run();
+            assert_eq!(slow.reg, fast.reg, "n = {}", n);
+            assert_eq!(n / 256, fast.reg[1]);
+        }
+    }
+
+    #[test]
+    fn test_rejects_jump_into_middle() {
+        let mut program = get_divisor_program(12);
+        // Jump straight to the inner loop, bypassing the initialisation of i.
+        program.instrs[0] = Instr::new(OpType::Seti, 2, 0, 5);
+        assert!(optimize(&program, 0, &[Range::exactly(0); 6]).is_empty());
+    }
+}
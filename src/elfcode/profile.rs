@@ -0,0 +1,173 @@
+// Optional execution profiling for `Machine`: how often each instruction runs, which way each
+// jump goes, and which values registers hold at chosen instructions.
+
+use super::{Program, Word};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub hits: Vec<u64>,
+    /// For instructions that write the ip register, how often control went somewhere other
+    /// than the following instruction.
+    pub taken: Vec<u64>,
+    /// For instructions that write the ip register, how often control fell through.
+    pub not_taken: Vec<u64>,
+    /// Value counts for (instruction, register) pairs, sampled before the instruction runs.
+    pub histograms: BTreeMap<(usize, usize), BTreeMap<Word, u64>>,
+    jumps: Vec<bool>,
+    num_regs: usize,
+}
+
+impl Profile {
+    pub fn new(program: &Program, num_regs: usize) -> Profile {
+        let len = program.len();
+        Profile {
+            hits: vec![0; len],
+            taken: vec![0; len],
+            not_taken: vec![0; len],
+            histograms: BTreeMap::new(),
+            jumps: program
+                .instrs
+                .iter()
+                .map(|i| Some(i.c) == program.ip_reg)
+                .collect(),
+            num_regs,
+        }
+    }
+
+    /// Record a histogram of the values of `reg` each time instruction `pc` executes.
+    pub fn watch(&mut self, pc: usize, reg: usize) -> Result<(), String> {
+        if pc >= self.hits.len() {
+            return Err(format!("No instruction {}", pc));
+        }
+        if reg >= self.num_regs {
+            return Err(format!("No register r{}", reg));
+        }
+        self.histograms.entry((pc, reg)).or_default();
+        Ok(())
+    }
+
+    pub fn record_entry(&mut self, pc: usize, reg: &[Word]) {
+        self.hits[pc] += 1;
+        for (&(at, r), histogram) in self.histograms.range_mut((pc, 0)..=(pc, usize::MAX)) {
+            debug_assert_eq!(at, pc);
+            *histogram.entry(reg[r]).or_insert(0) += 1;
+        }
+    }
+
+    /// Record where control went after instruction `pc`.  `native` marks a replaced idiom,
+    /// which always counts as a jump.
+    pub fn record_exit(&mut self, pc: usize, next_ip: usize, native: bool) {
+        if native || self.jumps[pc] {
+            if next_ip == pc + 1 {
+                self.not_taken[pc] += 1;
+            } else {
+                self.taken[pc] += 1;
+            }
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.hits.iter().sum()
+    }
+
+    /// The program listing annotated with hit and branch counts, followed by the histograms.
+    /// At most `max_values` of the most frequent values are shown for each histogram.
+    pub fn render(&self, program: &Program, max_values: usize) -> String {
+        let total = self.total().max(1);
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:>12} {:>6} {:>23}  instruction",
+            "hits", "%", "taken/not taken"
+        )
+        .unwrap();
+        for (pc, instr) in program.instrs.iter().enumerate() {
+            let hits = self.hits.get(pc).cloned().unwrap_or(0);
+            let branch = if self.taken[pc] + self.not_taken[pc] > 0 {
+                format!("{}/{}", self.taken[pc], self.not_taken[pc])
+            } else {
+                String::new()
+            };
+            writeln!(
+                out,
+                "{:>12} {:>6.2} {:>23}  {:>3}: {}",
+                hits,
+                100.0 * hits as f64 / total as f64,
+                branch,
+                pc,
+                instr
+            )
+            .unwrap();
+        }
+        for (&(pc, reg), histogram) in &self.histograms {
+            let samples: u64 = histogram.values().sum();
+            writeln!(
+                out,
+                "\nr{} at {}: {} samples, {} distinct values",
+                reg,
+                pc,
+                samples,
+                histogram.len()
+            )
+            .unwrap();
+            let mut values = histogram.iter().collect::<Vec<(&Word, &u64)>>();
+            values.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (value, count) in values.iter().take(max_values) {
+                writeln!(out, "{:>12} x{}", value, count).unwrap();
+            }
+            if values.len() > max_values {
+                writeln!(
+                    out,
+                    "{:>12}",
+                    format!("({} more)", values.len() - max_values)
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Machine;
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let program = Program::parse(
+            "#ip 2
+            seti 0 0 0
+            addi 0 1 0
+            gtri 0 2 1
+            addr 1 2 2
+            seti 0 0 2
+            seti 7 0 3",
+        );
+        let mut machine = Machine::new(program, 4);
+        machine.enable_profiling();
+        let profile = machine.profile.as_mut().unwrap();
+        profile.watch(2, 0).unwrap();
+        assert_eq!(Err("No register r4".to_string()), profile.watch(2, 4));
+        assert_eq!(Err("No instruction 6".to_string()), profile.watch(6, 0));
+        machine.run();
+
+        let profile = machine.profile.unwrap();
+        assert_eq!(vec![1, 3, 3, 3, 2, 1], profile.hits);
+        assert_eq!(vec![0, 0, 0, 1, 2, 0], profile.taken);
+        assert_eq!(vec![0, 0, 0, 2, 0, 0], profile.not_taken);
+        assert_eq!(
+            vec![(1, 1), (2, 1), (3, 1)],
+            profile.histograms[&(2, 0)]
+                .iter()
+                .map(|(&v, &c)| (v, c))
+                .collect::<Vec<(Word, u64)>>()
+        );
+        let listing = profile.render(&machine.program, 2);
+        assert!(listing.contains("  3: addr 1 2 2"));
+        assert!(listing.contains("r0 at 2: 3 samples, 3 distinct values"));
+        assert!(listing.contains("(1 more)"));
+    }
+}
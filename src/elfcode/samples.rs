@@ -0,0 +1,127 @@
+// Generates day 16 style puzzle input for a secret opcode mapping: `Before/instr/After`
+// samples followed by a test program, with instructions in numeric form.
+
+use super::{Instr, OpType, Word};
+use crate::rng::Rng;
+use std::fmt::Write;
+
+pub struct SampleGenerator {
+    /// The operation for each opcode number.
+    pub mapping: Vec<OpType>,
+    pub num_regs: usize,
+    /// Register values and immediates are drawn from `0..max_value`.
+    pub max_value: Word,
+    rng: Rng,
+}
+
+impl SampleGenerator {
+    /// A generator with a random mapping and four registers holding values below 4, like the
+    /// puzzle input.
+    pub fn new(seed: u64) -> SampleGenerator {
+        let mut rng = Rng::new(seed);
+        let mut mapping = OpType::ALL.to_vec();
+        for i in (1..mapping.len()).rev() {
+            mapping.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        SampleGenerator::with_mapping(mapping, rng)
+    }
+
+    pub fn with_mapping(mapping: Vec<OpType>, rng: Rng) -> SampleGenerator {
+        SampleGenerator {
+            mapping,
+            num_regs: 4,
+            max_value: 4,
+            rng,
+        }
+    }
+
+    fn value(&mut self) -> Word {
+        self.rng.below(self.max_value as u64) as Word
+    }
+
+    fn reg(&mut self) -> Word {
+        self.rng.below(self.num_regs as u64) as Word
+    }
+
+    /// A random instruction as (opcode, a, b, c), with operands valid for its operation.
+    pub fn instr(&mut self) -> (usize, Instr) {
+        let code = self.rng.below(self.mapping.len() as u64) as usize;
+        let op = self.mapping[code];
+        let a = if op.a_is_reg() {
+            self.reg()
+        } else {
+            self.value()
+        };
+        let b = if op.b_is_reg() {
+            self.reg()
+        } else {
+            self.value()
+        };
+        let c = self.reg() as usize;
+        (code, Instr::new(op, a, b, c))
+    }
+
+    pub fn sample(&mut self) -> String {
+        let before = (0..self.num_regs)
+            .map(|_| self.value())
+            .collect::<Vec<Word>>();
+        let (code, instr) = self.instr();
+        let mut after = before.clone();
+        instr.execute(&mut after);
+        format!(
+            "Before: {:?}\n{} {} {} {}\nAfter:  {:?}\n",
+            before, code, instr.a, instr.b, instr.c, after
+        )
+    }
+
+    /// `count` samples followed by a program of `len` instructions, laid out like the puzzle
+    /// input.
+    pub fn input(&mut self, count: usize, len: usize) -> String {
+        let mut out = (0..count)
+            .map(|_| self.sample())
+            .collect::<Vec<String>>()
+            .join("\n");
+        out.push_str("\n\n\n");
+        for _ in 0..len {
+            let (code, instr) = self.instr();
+            writeln!(out, "{} {} {} {}", code, instr.a, instr.b, instr.c).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let mut gen = SampleGenerator::with_mapping(vec![OpType::Seti; 16], Rng::new(1));
+        let sample = gen.sample();
+        let lines = sample.lines().collect::<Vec<&str>>();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("Before: ["));
+        assert!(lines[2].starts_with("After:  ["));
+
+        // Seti copies its first operand into its third.
+        let nums = lines[1]
+            .split(' ')
+            .map(|n| n.parse::<usize>().unwrap())
+            .collect::<Vec<usize>>();
+        let after = lines[2][9..lines[2].len() - 1]
+            .split(", ")
+            .map(|n| n.parse::<usize>().unwrap())
+            .collect::<Vec<usize>>();
+        assert_eq!(nums[1], after[nums[3]]);
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let input = SampleGenerator::new(7).input(3, 2);
+        assert_eq!(input, SampleGenerator::new(7).input(3, 2));
+        assert_ne!(input, SampleGenerator::new(8).input(3, 2));
+        assert!(input.contains("]\n\nBefore"));
+        assert!(input.contains("]\n\n\n\n"));
+        assert_eq!(3 * 3 + 2 + 3 + 2, input.lines().count());
+    }
+}
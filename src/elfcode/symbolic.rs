@@ -0,0 +1,736 @@
+// Symbolic execution: registers hold expressions over their initial values rather than
+// numbers.  Jumps must still resolve to constants, so execution follows a single path; loops
+// the optimizer recognises and simple counted loops are summarised in one step, unless they
+// contain the instruction being run to, and other loops are unrolled.
+//
+// In expressions, `rN` stands for the initial value of register N.
+
+use super::analysis::Range;
+use super::cfg::Cfg;
+use super::optimize::{self, Native};
+use super::{OpType, Program, Word};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Arithmetic wraps on overflow, as it does in a `Machine` with the default
+/// `Overflow::Wrapping`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    And,
+    Or,
+    Gt,
+    Eq,
+    Max,
+}
+
+impl BinOp {
+    fn eval(self, a: Word, b: Word) -> Word {
+        match self {
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::And => a & b,
+            BinOp::Or => a | b,
+            BinOp::Gt => (a > b) as Word,
+            BinOp::Eq => (a == b) as Word,
+            BinOp::Max => a.max(b),
+        }
+    }
+
+    fn commutes(self) -> bool {
+        !matches!(self, BinOp::Gt | BinOp::Sub)
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Gt => ">",
+            BinOp::Eq => "==",
+            BinOp::Max => "max",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Const(Word),
+    /// The initial value of a register.
+    Init(usize),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+    /// Left unevaluated even for constants, since that's usually the formula we're after.
+    SumOfDivisors(Box<Expr>),
+    /// `max(n, 0) / k`.
+    Div(Box<Expr>, Word),
+}
+
+impl Expr {
+    /// Combine two expressions, folding constants and simplifying identities.
+    pub fn bin(op: BinOp, a: Expr, b: Expr) -> Expr {
+        // Keep constants on the right of commutative operations.
+        let (a, b) = match (&a, &b) {
+            (Expr::Const(_), _) if op.commutes() => (b, a),
+            _ => (a, b),
+        };
+        match (op, &a, &b) {
+            (_, Expr::Const(x), Expr::Const(y)) => Expr::Const(op.eval(*x, *y)),
+            (BinOp::Add, _, Expr::Const(0))
+            | (BinOp::Sub, _, Expr::Const(0))
+            | (BinOp::Mul, _, Expr::Const(1))
+            | (BinOp::Or, _, Expr::Const(0)) => a,
+            (BinOp::Mul, _, Expr::Const(0)) | (BinOp::And, _, Expr::Const(0)) => Expr::Const(0),
+            (BinOp::Eq, _, _) if a == b => Expr::Const(1),
+            (BinOp::Gt, _, _) if a == b => Expr::Const(0),
+            (BinOp::And, _, _) | (BinOp::Or, _, _) | (BinOp::Max, _, _) if a == b => a,
+            // (x op c1) op c2 => x op (c1 op c2), for the associative operations.
+            (BinOp::Add, Expr::Bin(inner, x, c1), Expr::Const(c2))
+            | (BinOp::Mul, Expr::Bin(inner, x, c1), Expr::Const(c2))
+            | (BinOp::And, Expr::Bin(inner, x, c1), Expr::Const(c2))
+            | (BinOp::Or, Expr::Bin(inner, x, c1), Expr::Const(c2))
+                if *inner == op =>
+            {
+                match **c1 {
+                    Expr::Const(c1) => Expr::bin(op, (**x).clone(), Expr::Const(op.eval(c1, *c2))),
+                    _ => Expr::Bin(op, Box::new(a.clone()), Box::new(b.clone())),
+                }
+            }
+            _ => Expr::Bin(op, Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn as_const(&self) -> Option<Word> {
+        match *self {
+            Expr::Const(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn div(n: Expr, k: Word) -> Expr {
+        match n {
+            Expr::Const(n) => Expr::Const(n.max(0) / k),
+            n if k == 1 => Expr::bin(BinOp::Max, n, Expr::Const(0)),
+            n => Expr::Div(Box::new(n), k),
+        }
+    }
+
+    /// Replace each `rN` with `reg[N]`.
+    fn substitute(&self, reg: &[Expr]) -> Expr {
+        match self {
+            Expr::Const(_) => self.clone(),
+            Expr::Init(r) => reg[*r].clone(),
+            Expr::Bin(op, a, b) => Expr::bin(*op, a.substitute(reg), b.substitute(reg)),
+            Expr::SumOfDivisors(n) => Expr::SumOfDivisors(Box::new(n.substitute(reg))),
+            Expr::Div(n, k) => Expr::div(n.substitute(reg), *k),
+        }
+    }
+
+    /// Whether every `rN` in the expression satisfies `allowed`.
+    fn only_uses(&self, allowed: &dyn Fn(usize) -> bool) -> bool {
+        match self {
+            Expr::Const(_) => true,
+            Expr::Init(r) => allowed(*r),
+            Expr::Bin(_, a, b) => a.only_uses(allowed) && b.only_uses(allowed),
+            Expr::SumOfDivisors(n) | Expr::Div(n, _) => n.only_uses(allowed),
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expr::Bin(op, _, _) if *op != BinOp::Max => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Init(r) => write!(f, "r{}", r),
+            Expr::Bin(BinOp::Max, a, b) => write!(f, "max({}, {})", a, b),
+            Expr::Bin(op, a, b) => {
+                a.fmt_operand(f)?;
+                write!(f, " {} ", op.symbol())?;
+                b.fmt_operand(f)
+            }
+            Expr::SumOfDivisors(n) => write!(f, "sum_divisors({})", n),
+            Expr::Div(n, k) => {
+                n.fmt_operand(f)?;
+                write!(f, " / {}", k)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymError {
+    /// The jump at `pc` goes to an address that depends on the initial registers.
+    Branch { pc: usize, target: Expr },
+    /// The instruction at `pc` names a register that doesn't exist.
+    Register { pc: usize, reg: usize },
+    /// The program halted after this many steps without reaching the instruction.
+    Halted(usize),
+    /// The instruction wasn't reached within the step limit.
+    StepLimit(usize),
+}
+
+impl fmt::Display for SymError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SymError::Branch { pc, target } => {
+                write!(f, "Jump at {} depends on the input: ip = {}", pc, target)
+            }
+            SymError::Register { pc, reg } => write!(f, "No register r{} at {}", reg, pc),
+            SymError::Halted(steps) => write!(f, "Halted after {} steps", steps),
+            SymError::StepLimit(steps) => write!(f, "Not reached in {} steps", steps),
+        }
+    }
+}
+
+impl std::error::Error for SymError {}
+
+/// Registers that all hold their initial values.
+pub fn initial(num_regs: usize) -> Vec<Expr> {
+    (0..num_regs).map(Expr::Init).collect()
+}
+
+fn operand(reg: &[Expr], pc: usize, value: Word, is_reg: bool) -> Result<Expr, SymError> {
+    if !is_reg {
+        return Ok(Expr::Const(value));
+    }
+    match reg.get(value as usize) {
+        Some(e) if value >= 0 => Ok(e.clone()),
+        _ => Err(SymError::Register {
+            pc,
+            reg: value as usize,
+        }),
+    }
+}
+
+/// Execute the instruction at `pc`, with the ip register (if any) already set.
+fn execute(program: &Program, reg: &mut [Expr], pc: usize) -> Result<(), SymError> {
+    let instr = program.instrs[pc];
+    let a = operand(reg, pc, instr.a, instr.op.a_is_reg())?;
+    let b = operand(reg, pc, instr.b, instr.op.b_is_reg())?;
+    let value = match instr.op {
+        OpType::Setr | OpType::Seti => a,
+        OpType::Addr | OpType::Addi => Expr::bin(BinOp::Add, a, b),
+        OpType::Mulr | OpType::Muli => Expr::bin(BinOp::Mul, a, b),
+        OpType::Banr | OpType::Bani => Expr::bin(BinOp::And, a, b),
+        OpType::Borr | OpType::Bori => Expr::bin(BinOp::Or, a, b),
+        OpType::Gtir | OpType::Gtri | OpType::Gtrr => Expr::bin(BinOp::Gt, a, b),
+        OpType::Eqir | OpType::Eqri | OpType::Eqrr => Expr::bin(BinOp::Eq, a, b),
+    };
+    match reg.get_mut(instr.c) {
+        Some(r) => *r = value,
+        None => return Err(SymError::Register { pc, reg: instr.c }),
+    }
+    Ok(())
+}
+
+/// Apply a recognised loop to the registers, as `Native::execute` does, returning the next ip.
+fn summarise(native: &Native, reg: &mut [Expr]) -> usize {
+    match *native {
+        Native::SumOfDivisors {
+            i,
+            j,
+            t,
+            n,
+            acc,
+            ip_reg,
+            exit,
+        } => {
+            let n = reg[n].clone();
+            let sum = Expr::SumOfDivisors(Box::new(n.clone()));
+            reg[acc] = Expr::bin(BinOp::Add, reg[acc].clone(), sum);
+            let end = Expr::bin(
+                BinOp::Add,
+                Expr::bin(BinOp::Max, n, Expr::Const(1)),
+                Expr::Const(1),
+            );
+            reg[i] = end.clone();
+            reg[j] = end;
+            reg[t] = Expr::Const(1);
+            reg[ip_reg] = Expr::Const(exit as Word - 1);
+            exit
+        }
+        Native::Divide {
+            q,
+            t,
+            n,
+            k,
+            ip_reg,
+            exit,
+        } => {
+            reg[q] = Expr::div(reg[n].clone(), k);
+            reg[t] = Expr::Const(1);
+            reg[ip_reg] = Expr::Const(exit as Word - 1);
+            exit
+        }
+    }
+}
+
+/// A do-while loop with a straight-line body, which exits once an induction register stepping
+/// by a positive constant exceeds a loop-invariant bound:
+///
+/// ```text
+/// start:   body, including i += step
+/// end - 2: gtrr i n t  (or gtri i n t)
+/// end - 1: addr t ip ip
+/// end:     seti start-1 _ ip
+/// ```
+///
+/// Expressions are in terms of the registers on entry to an iteration.
+#[derive(Clone, Debug, PartialEq)]
+struct CountedLoop {
+    i: usize,
+    step: Word,
+    bound: Expr,
+    /// Registers that change by the same loop-invariant amount on every iteration.
+    linear: Vec<(usize, Expr)>,
+    /// Registers set from loop-invariant values, and so the same after every iteration.
+    recomputed: Vec<(usize, Expr)>,
+    t: usize,
+    ip_reg: usize,
+    end: usize,
+}
+
+impl CountedLoop {
+    /// Recognise a counted loop ending at `end`, returning it with its first instruction.
+    fn recognise(program: &Program, num_regs: usize, end: usize) -> Option<(usize, CountedLoop)> {
+        let ip_reg = program.ip_reg.filter(|&r| r < num_regs)?;
+        let instr = |pc: usize| program.instrs[pc];
+        let (jump, skip, cmp) = (
+            instr(end),
+            instr(end.checked_sub(1)?),
+            instr(end.checked_sub(2)?),
+        );
+        if jump.op != OpType::Seti || jump.c != ip_reg || jump.a < 0 {
+            return None;
+        }
+        let start = jump.a as usize + 1;
+        let t = cmp.c;
+        if start > end - 2
+            || skip.op != OpType::Addr
+            || skip.c != ip_reg
+            || (skip.a, skip.b) != (t as Word, ip_reg as Word)
+                && (skip.a, skip.b) != (ip_reg as Word, t as Word)
+            || t == ip_reg
+            || t >= num_regs
+            || (start..end - 2).any(|pc| instr(pc).c == ip_reg)
+        {
+            return None;
+        }
+
+        // One iteration's effect on each register.
+        let mut reg = initial(num_regs);
+        for pc in start..end - 2 {
+            reg[ip_reg] = Expr::Const(pc as Word);
+            execute(program, &mut reg, pc).ok()?;
+        }
+        let invariant = |r: usize| r != t && r != ip_reg && reg.get(r) == Some(&Expr::Init(r));
+        let i = cmp.a as usize;
+        let bound = match cmp.op {
+            OpType::Gtrr if cmp.b >= 0 && invariant(cmp.b as usize) => Expr::Init(cmp.b as usize),
+            OpType::Gtri => Expr::Const(cmp.b),
+            _ => return None,
+        };
+        let (mut linear, mut recomputed) = (vec![], vec![]);
+        for (r, e) in reg.iter().enumerate() {
+            if r == t || r == ip_reg || invariant(r) {
+                continue;
+            }
+            let step = match e {
+                Expr::Bin(BinOp::Add, a, b) if **a == Expr::Init(r) => Some(b),
+                Expr::Bin(BinOp::Add, a, b) if **b == Expr::Init(r) => Some(a),
+                _ => None,
+            };
+            match step {
+                Some(step) if step.only_uses(&invariant) => linear.push((r, (**step).clone())),
+                _ if e.only_uses(&invariant) => recomputed.push((r, e.clone())),
+                _ => return None,
+            }
+        }
+        let step = match linear.iter().find(|(r, _)| *r == i) {
+            Some((_, Expr::Const(step))) if *step > 0 => *step,
+            _ => return None,
+        };
+        let counted = CountedLoop {
+            i,
+            step,
+            bound,
+            linear,
+            recomputed,
+            t,
+            ip_reg,
+            end,
+        };
+        Some((start, counted))
+    }
+
+    /// Whether the induction register passes the bound without wrapping, as far as constants
+    /// show; symbolic values are assumed to stay clear of overflow.
+    fn passes_bound(&self, reg: &[Expr]) -> bool {
+        let fits = |value: Word| value.checked_add(self.step).is_some();
+        match (
+            reg[self.i].as_const(),
+            self.bound.substitute(reg).as_const(),
+        ) {
+            (Some(start), Some(bound)) => {
+                let (start, bound) = (i128::from(start), i128::from(bound));
+                let step = i128::from(self.step);
+                let last = start + ((bound - start).max(0) / step + 1) * step;
+                last <= i128::from(Word::MAX)
+            }
+            (Some(value), None) | (None, Some(value)) => fits(value),
+            (None, None) => true,
+        }
+    }
+
+    /// Apply every iteration of the loop to the registers, returning the next ip.
+    fn summarise(&self, reg: &mut [Expr]) -> usize {
+        let entry = reg.to_vec();
+        // The body always runs once; after that, once more for every `step` up to the bound.
+        let remaining = Expr::bin(
+            BinOp::Sub,
+            self.bound.substitute(&entry),
+            entry[self.i].clone(),
+        );
+        let iterations = Expr::bin(BinOp::Add, Expr::div(remaining, self.step), Expr::Const(1));
+        for (r, step) in &self.linear {
+            let total = Expr::bin(BinOp::Mul, iterations.clone(), step.substitute(&entry));
+            reg[*r] = Expr::bin(BinOp::Add, entry[*r].clone(), total);
+        }
+        for (r, e) in &self.recomputed {
+            reg[*r] = e.substitute(&entry);
+        }
+        reg[self.t] = Expr::Const(1);
+        reg[self.ip_reg] = Expr::Const(self.end as Word);
+        self.end + 1
+    }
+}
+
+/// Run from instruction 0 with the given registers until the instruction pointer reaches `pc`,
+/// returning the registers at that point.
+pub fn run_to(
+    program: &Program,
+    mut reg: Vec<Expr>,
+    pc: usize,
+    max_steps: usize,
+) -> Result<Vec<Expr>, SymError> {
+    let entry = reg
+        .iter()
+        .map(|e| match *e {
+            Expr::Const(value) => Range::exactly(value),
+            _ => Range::ANY,
+        })
+        .collect::<Vec<Range>>();
+    let natives = optimize::optimize(program, 0, &entry);
+    let loops = (0..program.len())
+        .filter_map(|end| CountedLoop::recognise(program, reg.len(), end))
+        .collect::<HashMap<usize, CountedLoop>>();
+    let mut ip = 0;
+    for steps in 0..max_steps {
+        if ip == pc {
+            return Ok(reg);
+        }
+        if ip >= program.len() {
+            return Err(SymError::Halted(steps));
+        }
+        // Loops containing the target are stepped through, so as to stop there.
+        let contains_pc = |exit: usize| ip < pc && pc < exit;
+        if let Some(native) = natives
+            .get(&ip)
+            .filter(|native| !contains_pc(native.exit()))
+        {
+            ip = summarise(native, &mut reg);
+            continue;
+        }
+        if let Some(counted) = loops
+            .get(&ip)
+            .filter(|counted| !contains_pc(counted.end + 1) && counted.passes_bound(&reg))
+        {
+            ip = counted.summarise(&mut reg);
+            continue;
+        }
+        let ip_reg = match program.ip_reg {
+            Some(ip_reg) if ip_reg < reg.len() => ip_reg,
+            Some(ip_reg) => {
+                return Err(SymError::Register {
+                    pc: ip,
+                    reg: ip_reg,
+                })
+            }
+            None => {
+                execute(program, &mut reg, ip)?;
+                ip += 1;
+                continue;
+            }
+        };
+        reg[ip_reg] = Expr::Const(ip as Word);
+        execute(program, &mut reg, ip)?;
+        ip = match reg[ip_reg].as_const() {
+            Some(next) if next + 1 < 0 => usize::MAX,
+            Some(next) => next as usize + 1,
+            None => {
+                return Err(SymError::Branch {
+                    pc: ip,
+                    target: reg[ip_reg].clone(),
+                })
+            }
+        };
+    }
+    Err(SymError::StepLimit(max_steps))
+}
+
+/// The effect of each basic block's straight-line body, as the registers it changes.
+pub fn block_effects(
+    program: &Program,
+    num_regs: usize,
+) -> Result<Vec<Vec<(usize, Expr)>>, SymError> {
+    Cfg::build(program)
+        .blocks
+        .iter()
+        .map(|block| {
+            let mut reg = initial(num_regs);
+            for pc in block.start..block.body_end {
+                if let Some(ip_reg) = program.ip_reg {
+                    if let Some(r) = reg.get_mut(ip_reg) {
+                        *r = Expr::Const(pc as Word);
+                    }
+                }
+                execute(program, &mut reg, pc)?;
+            }
+            Ok(reg
+                .into_iter()
+                .enumerate()
+                .filter(|(r, e)| Some(*r) != program.ip_reg && *e != Expr::Init(*r))
+                .collect())
+        })
+        .collect()
+}
+
+/// One line per register, `rN = expr`, skipping any that are unchanged.
+pub fn render(reg: &[Expr]) -> String {
+    reg.iter()
+        .enumerate()
+        .filter(|(r, e)| **e != Expr::Init(*r))
+        .map(|(r, e)| format!("r{} = {}\n", r, e))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elfcode::{Instr, Machine};
+
+    #[test]
+    fn test_simplify() {
+        let r1 = Expr::Init(1);
+        assert_eq!(
+            Expr::Const(7),
+            Expr::bin(BinOp::Add, Expr::Const(3), Expr::Const(4))
+        );
+        assert_eq!(r1, Expr::bin(BinOp::Add, Expr::Const(0), r1.clone()));
+        assert_eq!(
+            "r1 + 5",
+            Expr::bin(
+                BinOp::Add,
+                Expr::bin(BinOp::Add, r1.clone(), Expr::Const(2)),
+                Expr::Const(3)
+            )
+            .to_string()
+        );
+        assert_eq!(
+            "(r1 + 2) * r0",
+            Expr::bin(
+                BinOp::Mul,
+                Expr::bin(BinOp::Add, r1.clone(), Expr::Const(2)),
+                Expr::Init(0)
+            )
+            .to_string()
+        );
+        assert_eq!(Expr::Const(1), Expr::bin(BinOp::Eq, r1.clone(), r1));
+    }
+
+    #[test]
+    fn test_sum_of_divisors() {
+        // The day 19 loop, with setup that just adds 2 to r1.
+        let program = Program::parse(
+            "#ip 5
+            addi 5 16 5
+            seti 1 2 2
+            seti 1 0 4
+            mulr 2 4 3
+            eqrr 3 1 3
+            addr 3 5 5
+            addi 5 1 5
+            addr 2 0 0
+            addi 4 1 4
+            gtrr 4 1 3
+            addr 5 3 5
+            seti 2 4 5
+            addi 2 1 2
+            gtrr 2 1 3
+            addr 3 5 5
+            seti 1 1 5
+            mulr 5 5 5
+            addi 1 2 1
+            seti 0 6 5",
+        );
+        let reg = run_to(&program, initial(6), 16, 1000).unwrap();
+        assert_eq!(
+            "r0 = r0 + sum_divisors(r1 + 2)
+r1 = r1 + 2
+r2 = max(r1 + 2, 1) + 1
+r3 = 1
+r4 = max(r1 + 2, 1) + 1
+r5 = 15
+",
+            render(&reg)
+        );
+
+        let mut reg = initial(6);
+        reg[0] = Expr::Const(0);
+        reg[1] = Expr::Const(10);
+        let reg = run_to(&program, reg, 16, 1000).unwrap();
+        assert_eq!("sum_divisors(12)", reg[0].to_string());
+
+        // Stopping inside the loop runs it instead.
+        let reg = run_to(&program, initial(6), 3, 1000).unwrap();
+        assert_eq!(
+            (Expr::Const(1), Expr::Const(1)),
+            (reg[2].clone(), reg[4].clone())
+        );
+    }
+
+    #[test]
+    fn test_unrolled_loop() {
+        let program = Program::parse(
+            "#ip 4
+            seti 0 0 1
+            mulr 0 3 0
+            addi 1 1 1
+            gtri 1 2 2
+            addr 2 4 4
+            seti 0 0 4
+            seti 9 0 4",
+        );
+        let reg = run_to(&program, initial(5), 6, 1000).unwrap();
+        assert_eq!("((r0 * r3) * r3) * r3", reg[0].to_string());
+        assert_eq!(Expr::Const(3), reg[1]);
+    }
+
+    #[test]
+    fn test_counted_loop() {
+        // Steps r1 by 2 until it exceeds r2, adding r3 to r0 and setting r5 to r3 * r3 on the
+        // way.
+        let program = Program::parse(
+            "#ip 6
+            seti 0 0 4
+            addi 1 2 1
+            addr 0 3 0
+            mulr 3 3 5
+            gtrr 1 2 4
+            addr 4 6 6
+            seti 0 0 6
+            seti 9 0 6",
+        );
+        let reg = run_to(&program, initial(7), 7, 100).unwrap();
+        assert_eq!(
+            "r0 = r0 + (((r2 - r1) / 2 + 1) * r3)
+r1 = r1 + (((r2 - r1) / 2 + 1) * 2)
+r4 = 1
+r5 = r3 * r3
+r6 = 6
+",
+            render(&reg)
+        );
+
+        // The closed forms agree with running the loop, including when the bound is already
+        // passed on entry.
+        for &(r1, r2) in &[(0, 0), (0, 9), (3, 10), (7, -4), (-5, 5)] {
+            let mut machine = Machine::new(program.clone(), 7);
+            machine.reg = vec![11, r1, r2, 5, 0, 0, 0];
+            let reg = machine
+                .reg
+                .iter()
+                .map(|&value| Expr::Const(value))
+                .collect();
+            let reg = run_to(&program, reg, 7, 100).unwrap();
+            machine.run();
+            let expected = machine.reg[..6].iter().map(|&value| Expr::Const(value));
+            assert!(
+                expected.eq(reg[..6].iter().cloned()),
+                "r1 = {}, r2 = {}",
+                r1,
+                r2
+            );
+        }
+
+        // Stopping inside the loop runs it instead, stopping in the first iteration.
+        let reg = run_to(&program, initial(7), 3, 100).unwrap();
+        assert_eq!("r0 = r0 + r3\nr1 = r1 + 2\nr4 = 0\nr6 = 2\n", render(&reg));
+
+        // The counter would have to wrap to pass this bound, so the loop runs forever.
+        let mut program = program;
+        program.instrs[4] = Instr::new(OpType::Gtri, 1, Word::MAX, 4);
+        let mut reg = initial(7);
+        reg[1] = Expr::Const(0);
+        assert_eq!(Err(SymError::StepLimit(100)), run_to(&program, reg, 7, 100));
+    }
+
+    #[test]
+    fn test_errors() {
+        let program = Program::parse("#ip 3\ngtri 0 5 1\naddr 1 3 3\nseti 2 0 2\nseti 3 0 2");
+        assert_eq!(
+            Err(SymError::Branch {
+                pc: 1,
+                target: Expr::bin(
+                    BinOp::Add,
+                    Expr::bin(BinOp::Gt, Expr::Init(0), Expr::Const(5)),
+                    Expr::Const(1)
+                )
+            }),
+            run_to(&program, initial(4), 3, 100)
+        );
+        let mut reg = initial(4);
+        reg[0] = Expr::Const(9);
+        assert_eq!(
+            Expr::Const(2),
+            run_to(&program, reg.clone(), 3, 100).unwrap()[3]
+        );
+        assert_eq!(
+            Err(SymError::Halted(3)),
+            run_to(&program, reg.clone(), 2, 100)
+        );
+        assert_eq!(Err(SymError::StepLimit(1)), run_to(&program, reg, 3, 1));
+    }
+
+    #[test]
+    fn test_block_effects() {
+        let program = Program::parse("#ip 3\naddi 0 2 0\nmulr 0 1 2\nseti 2 0 3\nseti 7 0 1");
+        let effects = block_effects(&program, 4).unwrap();
+        assert_eq!(
+            vec![
+                (0, Expr::bin(BinOp::Add, Expr::Init(0), Expr::Const(2))),
+                (
+                    2,
+                    Expr::bin(
+                        BinOp::Mul,
+                        Expr::bin(BinOp::Add, Expr::Init(0), Expr::Const(2)),
+                        Expr::Init(1)
+                    )
+                ),
+            ],
+            effects[0]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+// Execution traces as JSON Lines, one object per instruction executed, and a replayer that
+// checks a run against a saved trace.
+
+use super::{Machine, Word};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Formatter;
+use std::io;
+use std::io::{BufRead, Write};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Counts from zero at the start of the trace.
+    pub step: u64,
+    pub ip: usize,
+    pub instr: String,
+    pub before: Vec<Word>,
+    pub after: Vec<Word>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {} ip={} {}: {:?} -> {:?}",
+            self.step, self.ip, self.instr, self.before, self.after
+        )
+    }
+}
+
+/// Execute one instruction, returning what it did, or None if the machine has halted.
+pub fn step(machine: &mut Machine, step: u64) -> Option<TraceEntry> {
+    let ip = machine.ip;
+    let instr = machine.current()?.to_string();
+    let before = machine.reg.clone();
+    machine.step();
+    Some(TraceEntry {
+        step,
+        ip,
+        instr,
+        before,
+        after: machine.reg.clone(),
+    })
+}
+
+/// Run until the machine halts or `limit` instructions have executed, writing each one to
+/// `out`.  Returns the number of instructions executed.
+pub fn write_trace<W: Write>(
+    machine: &mut Machine,
+    mut out: W,
+    limit: Option<u64>,
+) -> io::Result<u64> {
+    let mut steps = 0;
+    while limit != Some(steps) {
+        match step(machine, steps) {
+            Some(entry) => {
+                serde_json::to_writer(&mut out, &entry)?;
+                out.write_all(b"\n")?;
+            }
+            None => break,
+        }
+        steps += 1;
+    }
+    out.flush()?;
+    Ok(steps)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Divergence {
+    /// The first instruction that did something different.
+    Mismatch {
+        expected: Box<TraceEntry>,
+        actual: Box<TraceEntry>,
+    },
+    /// The machine halted before reaching this entry.
+    Halted { expected: Box<TraceEntry> },
+    /// The trace couldn't be read at this line.
+    Malformed { line: usize, message: String },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Divergence::Mismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Diverged at step {}\nexpected {}\n  actual {}",
+                    expected.step, expected, actual
+                )
+            }
+            Divergence::Halted { expected } => {
+                write!(f, "Halted at step {}, expected {}", expected.step, expected)
+            }
+            Divergence::Malformed { line, message } => {
+                write!(f, "Bad trace at line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+/// Run the machine alongside a saved trace, stopping at the first difference.  Returns the
+/// number of instructions checked.
+pub fn replay<R: BufRead>(machine: &mut Machine, trace: R) -> Result<u64, Divergence> {
+    let mut steps = 0;
+    for (index, line) in trace.lines().enumerate() {
+        let malformed = |message: String| Divergence::Malformed {
+            line: index + 1,
+            message,
+        };
+        let line = line.map_err(|e| malformed(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let expected: TraceEntry =
+            serde_json::from_str(&line).map_err(|e| malformed(e.to_string()))?;
+        match step(machine, steps) {
+            Some(ref actual) if *actual == expected => (),
+            Some(actual) => {
+                return Err(Divergence::Mismatch {
+                    expected: Box::new(expected),
+                    actual: Box::new(actual),
+                })
+            }
+            None => {
+                return Err(Divergence::Halted {
+                    expected: Box::new(expected),
+                })
+            }
+        }
+        steps += 1;
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::get_test_program;
+    use super::*;
+
+    fn get_test_machine() -> Machine {
+        Machine::new(get_test_program(), 6)
+    }
+
+    #[test]
+    fn test_write_trace() {
+        let mut out = Vec::new();
+        assert_eq!(
+            5,
+            write_trace(&mut get_test_machine(), &mut out, None).unwrap()
+        );
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(5, text.lines().count());
+        assert_eq!(
+            r#"{"step":0,"ip":0,"instr":"seti 5 0 1","before":[0,0,0,0,0,0],"after":[0,5,0,0,0,0]}"#,
+            text.lines().next().unwrap()
+        );
+
+        let mut out = Vec::new();
+        assert_eq!(
+            2,
+            write_trace(&mut get_test_machine(), &mut out, Some(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut out = Vec::new();
+        write_trace(&mut get_test_machine(), &mut out, None).unwrap();
+        assert_eq!(Ok(5), replay(&mut get_test_machine(), &out[..]));
+
+        let mut machine = get_test_machine();
+        machine.reg[2] = 1;
+        match replay(&mut machine, &out[..]) {
+            Err(Divergence::Mismatch { expected, actual }) => {
+                assert_eq!(0, expected.step);
+                assert_eq!(vec![0, 5, 1, 0, 0, 0], actual.after);
+            }
+            other => panic!("Expected a mismatch, got {:?}", other),
+        }
+
+        let mut machine = get_test_machine();
+        machine.program.instrs.truncate(4);
+        match replay(&mut machine, &out[..]) {
+            Err(Divergence::Halted { expected }) => assert_eq!(3, expected.step),
+            other => panic!("Expected a halt, got {:?}", other),
+        }
+
+        match replay(&mut get_test_machine(), &b"\n{\"step\": 0}\n"[..]) {
+            Err(Divergence::Malformed { line, .. }) => assert_eq!(2, line),
+            other => panic!("Expected a malformed trace, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// Register types for `Machine`, for running programs with the integer width and overflow
+// behaviour of a particular implementation.  Narrower machines can't be optimized or profiled,
+// but `Machine::try_step` reports arithmetic that doesn't fit as a `Trap` instead of silently
+// producing a different answer.
+
+use super::{Instr, Word};
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+
+/// What happens when addition or multiplication overflows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wrap around, as the puzzle's 24-bit masking assumes.
+    Wrapping,
+    /// Stop with a `Trap`.
+    Checked,
+    /// Clamp to the type's minimum or maximum.
+    Saturating,
+}
+
+pub trait Register: Copy + Debug + Display + PartialEq + PartialOrd {
+    const ZERO: Self;
+    const ONE: Self;
+
+    /// Converts an immediate operand, if it fits.
+    fn from_word(value: Word) -> Option<Self>;
+    /// Converts to an instruction pointer, if non-negative and in range.
+    fn to_index(self) -> Option<usize>;
+    fn from_index(index: usize) -> Option<Self>;
+    fn add(self, other: Self, overflow: Overflow) -> Option<Self>;
+    fn mul(self, other: Self, overflow: Overflow) -> Option<Self>;
+    fn and(self, other: Self) -> Self;
+    fn or(self, other: Self) -> Self;
+}
+
+macro_rules! impl_register {
+    ($($t:ty),*) => {
+        $(
+            impl Register for $t {
+                const ZERO: $t = 0;
+                const ONE: $t = 1;
+
+                fn from_word(value: Word) -> Option<$t> {
+                    use std::convert::TryFrom;
+                    <$t>::try_from(value).ok()
+                }
+
+                fn to_index(self) -> Option<usize> {
+                    use std::convert::TryFrom;
+                    usize::try_from(self).ok()
+                }
+
+                fn from_index(index: usize) -> Option<$t> {
+                    use std::convert::TryFrom;
+                    <$t>::try_from(index).ok()
+                }
+
+                fn add(self, other: $t, overflow: Overflow) -> Option<$t> {
+                    match overflow {
+                        Overflow::Wrapping => Some(self.wrapping_add(other)),
+                        Overflow::Checked => self.checked_add(other),
+                        Overflow::Saturating => Some(self.saturating_add(other)),
+                    }
+                }
+
+                fn mul(self, other: $t, overflow: Overflow) -> Option<$t> {
+                    match overflow {
+                        Overflow::Wrapping => Some(self.wrapping_mul(other)),
+                        Overflow::Checked => self.checked_mul(other),
+                        Overflow::Saturating => Some(self.saturating_mul(other)),
+                    }
+                }
+
+                fn and(self, other: $t) -> $t {
+                    self & other
+                }
+
+                fn or(self, other: $t) -> $t {
+                    self | other
+                }
+            }
+        )*
+    };
+}
+
+impl_register!(i32, i64, u32, u64);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrapKind<R> {
+    /// Addition or multiplication of these operands overflowed.
+    Overflow { a: R, b: R },
+    /// An immediate operand doesn't fit in the register type.
+    Immediate(Word),
+    /// The instruction names a register the machine doesn't have.
+    Register(Word),
+}
+
+/// Why execution stopped before the program halted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trap<R> {
+    pub pc: usize,
+    pub instr: Instr,
+    pub kind: TrapKind<R>,
+}
+
+impl<R: Register> fmt::Display for Trap<R> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}: ", self.pc, self.instr)?;
+        match &self.kind {
+            TrapKind::Overflow { a, b } => {
+                write!(f, "{} of {} and {} overflows", self.instr.op, a, b)
+            }
+            TrapKind::Immediate(value) => write!(f, "immediate {} out of range", value),
+            TrapKind::Register(reg) => write!(f, "register r{} out of range", reg),
+        }
+    }
+}
+
+impl<R: Register> std::error::Error for Trap<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elfcode::{Machine, Program};
+
+    // Doubles r1 until it exceeds 1000, then halts.
+    fn get_test_program() -> Program {
+        Program::parse(
+            "#ip 3
+            seti 1 0 1
+            addr 1 1 1
+            gtri 1 1000 2
+            addr 2 3 3
+            seti 0 0 3",
+        )
+    }
+
+    #[test]
+    fn test_widths_agree() {
+        let mut wide = Machine::<i64>::with_overflow(get_test_program(), 4, Overflow::Checked);
+        let mut narrow = Machine::<u32>::with_overflow(get_test_program(), 4, Overflow::Checked);
+        assert_eq!(Ok(40), wide.try_run());
+        assert_eq!(Ok(40), narrow.try_run());
+        assert_eq!(vec![0, 1024, 1, 4], wide.reg);
+        assert_eq!(vec![0, 1024, 1, 4], narrow.reg);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut program = get_test_program();
+        program.instrs[2].b = i64::from(i32::MAX);
+
+        let mut checked = Machine::<i32>::with_overflow(program.clone(), 4, Overflow::Checked);
+        let trap = checked.try_run().unwrap_err();
+        assert_eq!(1, trap.pc);
+        assert_eq!(
+            TrapKind::Overflow {
+                a: 1 << 30,
+                b: 1 << 30
+            },
+            trap.kind
+        );
+        assert_eq!(
+            "1: addr 1 1 1: addr of 1073741824 and 1073741824 overflows",
+            trap.to_string()
+        );
+        assert_eq!(1, checked.ip);
+        assert_eq!(1 << 30, checked.reg[1]);
+
+        // Wrapping goes negative, which fails the test and loops forever; saturating sticks at
+        // the maximum, which doesn't exceed it either.
+        let mut wrapping = Machine::<i32>::with_overflow(program.clone(), 4, Overflow::Wrapping);
+        while wrapping.reg[1] >= 0 {
+            wrapping.try_step().unwrap();
+        }
+        assert_eq!(i32::MIN, wrapping.reg[1]);
+        let mut saturating = Machine::<u64>::with_overflow(program, 4, Overflow::Saturating);
+        saturating.reg[1] = u64::MAX / 2 + 1;
+        saturating.ip = 1;
+        saturating.try_step().unwrap();
+        assert_eq!(u64::MAX, saturating.reg[1]);
+    }
+
+    #[test]
+    fn test_bad_operands() {
+        let mut machine =
+            Machine::<u64>::with_overflow(Program::parse("seti -1 0 0"), 1, Overflow::Checked);
+        assert_eq!(
+            Err(TrapKind::Immediate(-1)),
+            machine.try_step().map_err(|t| t.kind)
+        );
+        let mut machine =
+            Machine::<i32>::with_overflow(Program::parse("addr 0 4 0"), 4, Overflow::Checked);
+        assert_eq!(
+            Err(TrapKind::Register(4)),
+            machine.try_step().map_err(|t| t.kind)
+        );
+        let mut machine =
+            Machine::<i32>::with_overflow(Program::parse("seti 1 0 4"), 4, Overflow::Checked);
+        assert_eq!(
+            Err(TrapKind::Register(4)),
+            machine.try_step().map_err(|t| t.kind)
+        );
+        let mut machine = Machine::<i32>::with_overflow(
+            Program::parse("#ip 4\nseti 1 0 0"),
+            4,
+            Overflow::Checked,
+        );
+        assert_eq!(
+            Err(TrapKind::Register(4)),
+            machine.try_step().map_err(|t| t.kind)
+        );
+    }
+}
@@ -1,9 +1,12 @@
-#[macro_use]
-extern crate num_derive;
 extern crate regex;
 
 use aoc_runner_derive::aoc_lib;
 
+pub mod carts;
+pub mod combat;
+pub mod elfcode;
+pub mod rng;
+
 mod day13;
 mod day14;
 mod day15;